@@ -15,7 +15,7 @@ for (const account of accounts) {
 
 transactions.sort((a, b) => a.ledger_attr - b.ledger_attr)
 
-for (const { hash, ledger_attr, envelope_xdr, result_meta_xdr, result_xdr } of transactions) {
+for (const { hash, ledger_attr, created_at, envelope_xdr, result_meta_xdr, result_xdr } of transactions) {
     const tx = new Transaction(envelope_xdr, Networks.TESTNET);
 
     for (const op of tx.operations) {
@@ -24,7 +24,7 @@ for (const { hash, ledger_attr, envelope_xdr, result_meta_xdr, result_xdr } of t
         if (
             fn 
             // && fn.includes('offer')
-        ) await backfill(hash, ledger_attr, envelope_xdr, result_meta_xdr, result_xdr)
+        ) await backfill(hash, ledger_attr, created_at, envelope_xdr, result_meta_xdr, result_xdr)
     }
 }
 
@@ -45,7 +45,7 @@ async function get_transactions(account: string, cursor?: string) {
         return get_transactions(account, cursor)
 }
 
-async function backfill(hash: string, ledger_attr: number, envelope_xdr: string, result_meta_xdr: string, result_xdr: string) {
+async function backfill(hash: string, ledger_attr: number, created_at: string, envelope_xdr: string, result_meta_xdr: string, result_xdr: string) {
     await fetch('https://api.mercurydata.app/zephyr/execute', {
         method: 'POST',
         headers: {
@@ -58,6 +58,9 @@ async function backfill(hash: string, ledger_attr: number, envelope_xdr: string,
                 Function: {
                     fname: 'backfill',
                     arguments: JSON.stringify({
+                        hash,
+                        ledger: ledger_attr,
+                        timestamp: Math.floor(new Date(created_at).getTime() / 1000),
                         envelope_xdr,
                         result_meta_xdr,
                         result_xdr,
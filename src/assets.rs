@@ -0,0 +1,158 @@
+use zephyr_sdk::{
+    prelude::*,
+    soroban_sdk::xdr::{
+        ContractDataEntry, Hash, ScAddress, ScContractInstance, ScMap, ScMapEntry, ScString,
+        ScSymbol, ScVal,
+    },
+    EnvClient,
+};
+
+use crate::types::ZephyrAsset;
+
+// Pick up the metadata of an asset the first time an offer references it
+pub fn index_asset(env: &EnvClient, asset: &ScVal) {
+    if get_asset(&env, asset).is_some() {
+        return;
+    }
+
+    if let Some(metadata) = read_asset_metadata(&env, asset) {
+        env.put(&metadata);
+    }
+}
+
+pub fn get_asset(env: &EnvClient, asset: &ScVal) -> Option<ZephyrAsset> {
+    env.read_filter()
+        .column_equal_to_xdr("address", asset)
+        .read::<ZephyrAsset>()
+        .unwrap()
+        .into_iter()
+        .next()
+}
+
+/* SACs and most tokens keep `METADATA { decimal, name, symbol }` in their instance storage.
+    Anything which doesn't can be added through `seed_asset` instead.
+*/
+pub fn read_asset_metadata(env: &EnvClient, asset: &ScVal) -> Option<ZephyrAsset> {
+    let contract = match asset {
+        ScVal::Address(ScAddress::Contract(Hash(contract))) => *contract,
+        _ => return None,
+    };
+
+    let ContractDataEntry { val, .. } = env.read_contract_instance(contract).ok()??;
+
+    let storage = match val {
+        ScVal::ContractInstance(ScContractInstance {
+            storage: Some(ScMap(storage)),
+            ..
+        }) => storage,
+        _ => return None,
+    };
+
+    let metadata = storage
+        .iter()
+        .find(|ScMapEntry { key, .. }| symbol_eq(key, "METADATA"))
+        .map(|ScMapEntry { val, .. }| val)?;
+
+    let mut decimals = None;
+    let mut name = None;
+    let mut symbol = None;
+
+    if let ScVal::Map(Some(ScMap(fields))) = metadata {
+        for ScMapEntry { key, val } in fields.iter() {
+            match val {
+                ScVal::U32(val) if symbol_eq(key, "decimal") => decimals = Some(*val),
+                ScVal::String(_) if symbol_eq(key, "name") => name = Some(val.clone()),
+                ScVal::String(_) if symbol_eq(key, "symbol") => symbol = Some(val.clone()),
+                _ => {}
+            }
+        }
+    }
+
+    Some(ZephyrAsset {
+        address: asset.clone(),
+        name: name?,
+        symbol: symbol?,
+        decimals: decimals?,
+    })
+}
+
+fn symbol_eq(val: &ScVal, name: &str) -> bool {
+    match val {
+        ScVal::Symbol(ScSymbol(symbol)) => symbol.to_utf8_string_lossy() == name,
+        _ => false,
+    }
+}
+
+pub fn asset_code(asset: &ZephyrAsset) -> String {
+    match &asset.symbol {
+        ScVal::String(ScString(symbol)) => symbol.to_utf8_string_lossy(),
+        _ => String::new(),
+    }
+}
+
+// No i128 has more than 39 digits, so more decimals than this can't mean anything
+pub const MAX_DECIMALS: u32 = 38;
+
+// e.g. 12_5000000 with 7 decimals is "12.5", decimals past `MAX_DECIMALS` count as `MAX_DECIMALS`
+pub fn format_amount(amount: i128, decimals: u32) -> String {
+    let digits = amount.unsigned_abs().to_string();
+    let decimals = decimals.min(MAX_DECIMALS) as usize;
+    let sign = if amount < 0 { "-" } else { "" };
+
+    if decimals == 0 {
+        return format!("{}{}", sign, digits);
+    }
+
+    let digits = format!("{:0>width$}", digits, width = decimals + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals);
+    let fraction = fraction.trim_end_matches('0');
+
+    if fraction.is_empty() {
+        format!("{}{}", sign, whole)
+    } else {
+        format!("{}{}.{}", sign, whole, fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_amount;
+
+    #[test]
+    fn formats_amounts_in_the_asset_decimals() {
+        assert_eq!(format_amount(12_5000000, 7), "12.5");
+        assert_eq!(format_amount(10_0000000, 7), "10");
+        assert_eq!(format_amount(1, 7), "0.0000001");
+    }
+
+    #[test]
+    fn formats_zero() {
+        assert_eq!(format_amount(0, 7), "0");
+        assert_eq!(format_amount(0, 0), "0");
+    }
+
+    #[test]
+    fn formats_negative_amounts() {
+        assert_eq!(format_amount(-12_5000000, 7), "-12.5");
+        assert_eq!(format_amount(-1, 7), "-0.0000001");
+    }
+
+    #[test]
+    fn saturates_absurd_decimals() {
+        assert_eq!(format_amount(1, u32::MAX), format_amount(1, 38));
+        assert_eq!(
+            format_amount(i128::MAX, 1_000_000_000),
+            "1.70141183460469231731687303715884105727"
+        );
+        assert_eq!(
+            format_amount(i128::MIN, u32::MAX),
+            "-1.70141183460469231731687303715884105728"
+        );
+    }
+
+    #[test]
+    fn formats_amounts_without_decimals() {
+        assert_eq!(format_amount(125, 0), "125");
+        assert_eq!(format_amount(-125, 0), "-125");
+    }
+}
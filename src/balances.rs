@@ -0,0 +1,344 @@
+use sha2::{Digest, Sha256};
+use zephyr_sdk::{
+    prelude::*,
+    soroban_sdk::xdr::{
+        AccountEntry, Asset, ContractDataEntry, ContractIdPreimage, Hash, HashIdPreimage,
+        HashIdPreimageContractId, LedgerEntry, LedgerEntryChange, LedgerEntryChanges,
+        LedgerEntryData, LedgerKey, LedgerKeyAccount, LedgerKeyContractData, LedgerKeyTrustLine,
+        ScAddress, ScMap, ScMapEntry, ScSymbol, ScVal, ScVec, TransactionMeta, TransactionMetaV3,
+        TrustLineAsset, TrustLineEntry, WriteXdr,
+    },
+    EnvClient,
+};
+
+use crate::{
+    i128_to_scval, scval_to_i128,
+    types::{
+        TransactionContext, ZephyrBidderAddress, ZephyrBidderBalance, ZephyrBidderBalanceAmount,
+    },
+    validate_bidder_offers, NETWORK_PASSPHRASE,
+};
+
+/* Bids are only fillable if the bidder holds enough of the asset they're bidding with.
+    A SAC keeps contract balances in its own `Balance` entries, but account balances live in
+    the account (native) or trustline entries, so all three are followed for known bidders.
+*/
+pub fn process_balances(
+    env: &EnvClient,
+    ctx: &TransactionContext,
+    bidders: &Vec<ZephyrBidderBalance>,
+    tx_apply_processing: &TransactionMeta,
+) {
+    if bidders.len() == 0 {
+        return;
+    }
+
+    if let TransactionMeta::V3(TransactionMetaV3 {
+        tx_changes_before,
+        operations,
+        tx_changes_after,
+        ..
+    }) = tx_apply_processing
+    {
+        process_balance_changes(&env, ctx, bidders, tx_changes_before);
+
+        for operation in operations.iter() {
+            process_balance_changes(&env, ctx, bidders, &operation.changes);
+        }
+
+        process_balance_changes(&env, ctx, bidders, tx_changes_after);
+    }
+}
+
+// Everyone with a bid out, small enough to read once per ledger
+pub fn read_bidder_addresses(env: &EnvClient) -> Vec<ScVal> {
+    let mut addresses: Vec<ScVal> = Vec::new();
+
+    for bidder in env.read::<ZephyrBidderAddress>() {
+        if !addresses.contains(&bidder.address) {
+            addresses.push(bidder.address);
+        }
+    }
+
+    addresses
+}
+
+/* Only the bidders whose balances the transaction touched are loaded.
+    Holders are checked against `addresses` first, so transactions without a bidder in them
+    don't read anything.
+*/
+pub fn read_bidders(
+    env: &EnvClient,
+    addresses: &Vec<ScVal>,
+    fee_processing: &LedgerEntryChanges,
+    tx_apply_processing: &TransactionMeta,
+) -> Vec<ZephyrBidderBalance> {
+    if addresses.len() == 0 {
+        return Vec::new();
+    }
+
+    let mut all_changes = vec![fee_processing];
+
+    if let TransactionMeta::V3(TransactionMetaV3 {
+        tx_changes_before,
+        operations,
+        tx_changes_after,
+        ..
+    }) = tx_apply_processing
+    {
+        all_changes.push(tx_changes_before);
+        all_changes.extend(operations.iter().map(|operation| &operation.changes));
+        all_changes.push(tx_changes_after);
+    }
+
+    let mut holders: Vec<ScVal> = Vec::new();
+
+    for change in all_changes.iter().flat_map(|changes| changes.iter()) {
+        let holder = match change {
+            LedgerEntryChange::Created(LedgerEntry { data, .. })
+            | LedgerEntryChange::Updated(LedgerEntry { data, .. }) => get_entry_holder(data),
+            LedgerEntryChange::Removed(key) => get_key_holder(key),
+            _ => None,
+        };
+
+        if let Some(holder) = holder {
+            if addresses.contains(&holder) && !holders.contains(&holder) {
+                holders.push(holder);
+            }
+        }
+    }
+
+    holders
+        .iter()
+        .flat_map(|holder| {
+            env.read_filter()
+                .column_equal_to_xdr("address", holder)
+                .read::<ZephyrBidderBalance>()
+                .unwrap()
+        })
+        .collect()
+}
+
+fn get_entry_holder(data: &LedgerEntryData) -> Option<ScVal> {
+    match data {
+        LedgerEntryData::Account(AccountEntry { account_id, .. })
+        | LedgerEntryData::Trustline(TrustLineEntry { account_id, .. }) => {
+            Some(ScVal::Address(ScAddress::Account(account_id.clone())))
+        }
+        LedgerEntryData::ContractData(ContractDataEntry { key, .. }) => get_balance_holder(key),
+        _ => None,
+    }
+}
+
+fn get_key_holder(key: &LedgerKey) -> Option<ScVal> {
+    match key {
+        LedgerKey::Account(LedgerKeyAccount { account_id })
+        | LedgerKey::Trustline(LedgerKeyTrustLine { account_id, .. }) => {
+            Some(ScVal::Address(ScAddress::Account(account_id.clone())))
+        }
+        LedgerKey::ContractData(LedgerKeyContractData { key, .. }) => get_balance_holder(key),
+        _ => None,
+    }
+}
+
+pub fn process_balance_changes(
+    env: &EnvClient,
+    ctx: &TransactionContext,
+    bidders: &Vec<ZephyrBidderBalance>,
+    changes: &LedgerEntryChanges,
+) {
+    for change in changes.iter() {
+        let balance = match change {
+            LedgerEntryChange::Created(LedgerEntry { data, .. })
+            | LedgerEntryChange::Updated(LedgerEntry { data, .. }) => get_balance(bidders, data),
+            LedgerEntryChange::Removed(key) => get_removed_balance(bidders, key),
+            _ => None,
+        };
+
+        if let Some((address, asset, balance)) = balance {
+            env.update()
+                .column_equal_to_xdr("address", &address)
+                .column_equal_to_xdr("asset", &asset)
+                .execute(&ZephyrBidderBalanceAmount {
+                    balance: i128_to_scval(balance),
+                    ledger: ctx.ledger,
+                })
+                .unwrap();
+
+            validate_bidder_offers(&env, &address, &asset);
+        }
+    }
+}
+
+// (holder, asset, balance) if the entry is a balance some bidder is being tracked for
+fn get_balance(
+    bidders: &Vec<ZephyrBidderBalance>,
+    data: &LedgerEntryData,
+) -> Option<(ScVal, ScVal, i128)> {
+    let (address, asset, balance) = match data {
+        LedgerEntryData::Account(AccountEntry {
+            account_id,
+            balance,
+            ..
+        }) => {
+            let address = ScVal::Address(ScAddress::Account(account_id.clone()));
+
+            is_bidder(bidders, &address)?;
+
+            (address, get_sac_address(Asset::Native), *balance as i128)
+        }
+        LedgerEntryData::Trustline(TrustLineEntry {
+            account_id,
+            asset,
+            balance,
+            ..
+        }) => {
+            let address = ScVal::Address(ScAddress::Account(account_id.clone()));
+
+            is_bidder(bidders, &address)?;
+
+            (
+                address,
+                get_sac_address(trustline_asset(asset)?),
+                *balance as i128,
+            )
+        }
+        LedgerEntryData::ContractData(ContractDataEntry {
+            contract, key, val, ..
+        }) => {
+            let address = get_balance_holder(key)?;
+
+            is_bidder(bidders, &address)?;
+
+            (
+                address,
+                ScVal::Address(contract.clone()),
+                get_balance_amount(val)?,
+            )
+        }
+        _ => return None,
+    };
+
+    bidders
+        .iter()
+        .any(|bidder| bidder.address == address && bidder.asset == asset)
+        .then_some((address, asset, balance))
+}
+
+fn get_removed_balance(
+    bidders: &Vec<ZephyrBidderBalance>,
+    key: &LedgerKey,
+) -> Option<(ScVal, ScVal, i128)> {
+    let (address, asset) = match key {
+        LedgerKey::Account(LedgerKeyAccount { account_id }) => (
+            ScVal::Address(ScAddress::Account(account_id.clone())),
+            get_sac_address(Asset::Native),
+        ),
+        LedgerKey::Trustline(LedgerKeyTrustLine { account_id, asset }) => (
+            ScVal::Address(ScAddress::Account(account_id.clone())),
+            get_sac_address(trustline_asset(asset)?),
+        ),
+        LedgerKey::ContractData(LedgerKeyContractData { contract, key, .. }) => {
+            (get_balance_holder(key)?, ScVal::Address(contract.clone()))
+        }
+        _ => return None,
+    };
+
+    bidders
+        .iter()
+        .any(|bidder| bidder.address == address && bidder.asset == asset)
+        .then_some((address, asset, 0))
+}
+
+fn is_bidder(bidders: &Vec<ZephyrBidderBalance>, address: &ScVal) -> Option<()> {
+    bidders
+        .iter()
+        .any(|bidder| bidder.address == *address)
+        .then_some(())
+}
+
+fn trustline_asset(asset: &TrustLineAsset) -> Option<Asset> {
+    match asset {
+        TrustLineAsset::CreditAlphanum4(asset) => Some(Asset::CreditAlphanum4(asset.clone())),
+        TrustLineAsset::CreditAlphanum12(asset) => Some(Asset::CreditAlphanum12(asset.clone())),
+        _ => None,
+    }
+}
+
+// The SAC's contract id is derived from the asset it wraps
+fn get_sac_address(asset: Asset) -> ScVal {
+    let preimage = HashIdPreimage::ContractId(HashIdPreimageContractId {
+        network_id: Hash(Sha256::digest(NETWORK_PASSPHRASE.as_bytes()).into()),
+        contract_id_preimage: ContractIdPreimage::Asset(asset),
+    });
+    let contract_id = Sha256::digest(WriteXdr::to_xdr(&preimage, Limits::none()).unwrap());
+
+    ScVal::Address(ScAddress::Contract(Hash(contract_id.into())))
+}
+
+// Token balances are keyed by `Balance(holder)`
+fn get_balance_holder(key: &ScVal) -> Option<ScVal> {
+    if let ScVal::Vec(Some(ScVec(parts))) = key {
+        if let [ScVal::Symbol(ScSymbol(name)), ScVal::Address(holder)] = parts.as_slice() {
+            if name.to_utf8_string_lossy() == "Balance" {
+                return Some(ScVal::Address(holder.clone()));
+            }
+        }
+    }
+
+    None
+}
+
+// SACs store `{ amount, authorized, clawback }`, simpler tokens just the amount
+fn get_balance_amount(val: &ScVal) -> Option<i128> {
+    match val {
+        ScVal::Map(Some(ScMap(entries))) => {
+            entries
+                .iter()
+                .find_map(|ScMapEntry { key, val }| match key {
+                    ScVal::Symbol(ScSymbol(name)) if name.to_utf8_string_lossy() == "amount" => {
+                        scval_to_i128(val)
+                    }
+                    _ => None,
+                })
+        }
+        val => scval_to_i128(val),
+    }
+}
+
+/* Start following a bidder's balance of an asset once they've bid with it.
+    Bidders are loaded before the transaction is processed, so the bid's own operation is
+    checked here in case it moved the bidder's balance.
+*/
+pub fn track_bidder(
+    env: &EnvClient,
+    ctx: &TransactionContext,
+    changes: &LedgerEntryChanges,
+    address: &ScVal,
+    asset: &ScVal,
+) {
+    let existing = env
+        .read_filter()
+        .column_equal_to_xdr("address", address)
+        .column_equal_to_xdr("asset", asset)
+        .read::<ZephyrBidderBalance>()
+        .unwrap();
+
+    if existing.len() == 0 {
+        env.put(&ZephyrBidderBalance {
+            address: address.clone(),
+            asset: asset.clone(),
+            balance: ScVal::Void, // unknown until the balance is next written
+            ledger: 0,
+        });
+    }
+
+    let bidder = ZephyrBidderBalance {
+        address: address.clone(),
+        asset: asset.clone(),
+        balance: ScVal::Void,
+        ledger: 0,
+    };
+
+    process_balance_changes(&env, ctx, &vec![bidder], changes);
+}
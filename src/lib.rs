@@ -1,11 +1,17 @@
 use core::str::FromStr;
 
 use colorglyph::types::{Glyph, Offer, StorageKey};
+use sales::RemovedOffer;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use types::{
-    Offers, ZephyrColor, ZephyrColorAmount, ZephyrColorEmpty, ZephyrGlyph, ZephyrGlyphEmpty,
-    ZephyrGlyphMinter, ZephyrGlyphNoColors, ZephyrGlyphOwner, ZephyrGlyphWidthLengthColors,
-    ZephyrOffer, ZephyrOfferActive, ZephyrOfferEmpty, ZephyrOfferNoActive,
+    MatchStatus, MintState, OfferAsset, OfferStatus, OfferValidity, Offers, TransactionContext,
+    ZephyrAsset, ZephyrBidderBalance, ZephyrColor, ZephyrColorAmount, ZephyrColorEmpty,
+    ZephyrGlyph, ZephyrGlyphColor, ZephyrGlyphColorCoverage, ZephyrGlyphEmpty, ZephyrGlyphMint,
+    ZephyrGlyphMinter, ZephyrGlyphNoColors, ZephyrGlyphOwner, ZephyrGlyphPainted,
+    ZephyrLatestLedger, ZephyrMatch, ZephyrOffer, ZephyrOfferClosed, ZephyrOfferEmpty,
+    ZephyrOfferEvent, ZephyrOfferEventEmpty, ZephyrOfferNoStatus, ZephyrOfferPosted,
+    ZephyrOfferStatus, ZephyrOfferValidity, ZephyrSale, ZephyrSaleEmpty,
 };
 use zephyr_sdk::{
     prelude::*,
@@ -17,15 +23,23 @@ use zephyr_sdk::{
             Int128Parts, InvokeContractArgs, InvokeHostFunctionOp, InvokeHostFunctionResult,
             LedgerEntry, LedgerEntryChange, LedgerEntryChanges, LedgerEntryData, LedgerKey,
             LedgerKeyContractData, Operation, OperationBody, OperationMeta, OperationResult,
-            OperationResultTr, PublicKey, ScAddress, ScBytes, ScVal, ToXdr, TransactionEnvelope,
-            TransactionMeta, TransactionMetaV3, TransactionResult, TransactionResultMeta,
-            TransactionResultPair, TransactionResultResult, TransactionV1Envelope, Uint256, VecM,
+            OperationResultTr, PublicKey, ScAddress, ScBytes, ScString, ScVal, ScVec, ToXdr,
+            TransactionEnvelope, TransactionMeta, TransactionMetaV3, TransactionResult,
+            TransactionResultMeta, TransactionResultPair, TransactionResultResult,
+            TransactionV1Envelope, Uint256, VecM, WriteXdr,
         },
-        Address, Bytes, Vec as SorobanVec,
+        Address, Bytes, BytesN, Vec as SorobanVec,
     },
-    ContractDataEntry, EnvClient,
+    EnvClient,
 };
 
+mod assets;
+mod balances;
+mod matches;
+mod pixels;
+mod render;
+mod sales;
+mod swaps;
 mod types;
 
 /* TODO clean up the code
@@ -39,28 +53,107 @@ pub const CONTRACT_ADDRESS: [u8; 32] = [
     162, 111, 254, 172, 91, 128, 129, 68, 223, 102, 102, 140,
 ];
 
+// Needed to derive SAC addresses from the assets they wrap
+pub const NETWORK_PASSPHRASE: &str = "Test SDF Network ; September 2015";
+
 #[no_mangle]
 pub extern "C" fn on_close() {
     let env = EnvClient::new();
+    let ledger = env.reader().ledger_sequence();
+    let timestamp = env.reader().ledger_timestamp();
+
+    put_latest_ledger(&env, ledger, timestamp);
+
+    // Read once per ledger rather than per transaction, most transactions involve no bidder
+    let mut bidders = balances::read_bidder_addresses(&env);
 
     for (transaction_envelope, transaction_result_meta) in env.reader().envelopes_with_meta().iter()
     {
-        process_transaction(&env, transaction_envelope, transaction_result_meta);
+        process_transaction(
+            &env,
+            ledger,
+            timestamp,
+            &bidders,
+            transaction_envelope,
+            transaction_result_meta,
+        );
+
+        // A bid in this transaction may have added a bidder for the ones after it
+        if writes_contract_data(&transaction_result_meta.tx_apply_processing) {
+            bidders = balances::read_bidder_addresses(&env);
+        }
+    }
+}
+
+// Backfills replay older ledgers, so this only ever moves forward
+fn put_latest_ledger(env: &EnvClient, ledger: u32, timestamp: u64) {
+    let latest = ZephyrLatestLedger {
+        ledger,
+        timestamp: ScVal::U64(timestamp),
+    };
+
+    match env.read::<ZephyrLatestLedger>().first() {
+        None => env.put(&latest),
+        Some(existing) if existing.ledger < ledger => env
+            .update()
+            .column_equal_to("ledger", existing.ledger)
+            .execute(&latest)
+            .unwrap(),
+        Some(_) => {}
+    }
+}
+
+// Whether any of the transaction's operations wrote to the Colorglyph contract's storage
+fn writes_contract_data(tx_apply_processing: &TransactionMeta) -> bool {
+    let contract = ScAddress::Contract(Hash(CONTRACT_ADDRESS));
+
+    match tx_apply_processing {
+        TransactionMeta::V3(TransactionMetaV3 { operations, .. }) => operations
+            .iter()
+            .flat_map(|operation| operation.changes.iter())
+            .any(|change| match change {
+                LedgerEntryChange::Created(LedgerEntry {
+                    data: LedgerEntryData::ContractData(entry),
+                    ..
+                })
+                | LedgerEntryChange::Updated(LedgerEntry {
+                    data: LedgerEntryData::ContractData(entry),
+                    ..
+                }) => entry.contract == contract,
+                _ => false,
+            }),
+        _ => false,
     }
 }
 
 fn process_transaction(
     env: &EnvClient,
+    ledger: u32,
+    timestamp: u64,
+    bidder_addresses: &Vec<ScVal>,
     transaction_envelope: &TransactionEnvelope,
     transaction_result_meta: &TransactionResultMeta,
 ) {
     let TransactionResultMeta {
         result,
+        fee_processing,
         tx_apply_processing,
-        ..
     } = transaction_result_meta;
-    let TransactionResultPair { result, .. } = result;
+    let TransactionResultPair {
+        transaction_hash,
+        result,
+    } = result;
     let TransactionResult { result, .. } = result;
+    let ctx = TransactionContext {
+        ledger,
+        timestamp,
+        tx_hash: ScVal::Bytes(ScBytes(transaction_hash.0.to_vec().try_into().unwrap())),
+    };
+    let bidders =
+        &balances::read_bidders(&env, bidder_addresses, fee_processing, tx_apply_processing);
+
+    // Fees are charged whether or not the transaction went through
+    balances::process_balance_changes(&env, &ctx, bidders, fee_processing);
 
     match result {
         TransactionResultResult::TxFeeBumpInnerSuccess(tx) => {
@@ -68,17 +161,28 @@ fn process_transaction(
             let InnerTransactionResult { result, .. } = result;
 
             match result {
-                InnerTransactionResultResult::TxSuccess(results) => process_operation_result(
-                    &env,
-                    results,
-                    transaction_envelope,
-                    tx_apply_processing,
-                ),
+                InnerTransactionResultResult::TxSuccess(results) => {
+                    process_operation_result(
+                        &env,
+                        &ctx,
+                        results,
+                        transaction_envelope,
+                        tx_apply_processing,
+                    );
+                    balances::process_balances(&env, &ctx, bidders, tx_apply_processing);
+                }
                 _ => {}
             }
         }
         TransactionResultResult::TxSuccess(results) => {
-            process_operation_result(&env, results, transaction_envelope, tx_apply_processing)
+            process_operation_result(
+                &env,
+                &ctx,
+                results,
+                transaction_envelope,
+                tx_apply_processing,
+            );
+            balances::process_balances(&env, &ctx, bidders, tx_apply_processing);
         }
         _ => {}
     }
@@ -86,6 +190,7 @@ fn process_transaction(
 
 fn process_operation_result(
     env: &EnvClient,
+    ctx: &TransactionContext,
     results: &VecM<OperationResult>,
     tx_envelope: &TransactionEnvelope,
     tx_apply_processing: &TransactionMeta,
@@ -109,7 +214,7 @@ fn process_operation_result(
                                             match body {
                                                 OperationBody::InvokeHostFunction(op) => {
                                                     process_invoke_host_function_op(
-                                                        &env, op, changes,
+                                                        &env, ctx, op, changes,
                                                     )
                                                 }
                                                 _ => {}
@@ -129,7 +234,7 @@ fn process_operation_result(
                                                     match body {
                                                         OperationBody::InvokeHostFunction(op) => {
                                                             process_invoke_host_function_op(
-                                                                &env, op, changes,
+                                                                &env, ctx, op, changes,
                                                             )
                                                         }
                                                         _ => {}
@@ -155,6 +260,7 @@ fn process_operation_result(
 
 fn process_invoke_host_function_op(
     env: &EnvClient,
+    ctx: &TransactionContext,
     op: &InvokeHostFunctionOp,
     changes: &LedgerEntryChanges,
 ) {
@@ -164,9 +270,9 @@ fn process_invoke_host_function_op(
         HostFunction::InvokeContract(op) => {
             let InvokeContractArgs {
                 contract_address,
+                args,
                 ..
                 // function_name,
-                // args,
             } = op;
 
             if *contract_address == ScAddress::Contract(Hash(CONTRACT_ADDRESS)) {
@@ -182,15 +288,20 @@ fn process_invoke_host_function_op(
 
                 // if function_name.to_string() == String::from_str("hello").unwrap() {}
 
+                // Fills need to be known up front to tell why offers went away
+                let sales = process_sales(&env, ctx, args, changes);
+
                 for change in changes.iter() {
                     match change {
                         LedgerEntryChange::Created(LedgerEntry { data, .. }) => {
-                            process_ledger_entry_data(&env, data, None)
+                            process_ledger_entry_data(&env, ctx, data, changes, &sales)
                         }
                         LedgerEntryChange::Updated(LedgerEntry { data, .. }) => {
-                            process_ledger_entry_data(&env, data, Some(changes))
+                            process_ledger_entry_data(&env, ctx, data, changes, &sales)
+                        }
+                        LedgerEntryChange::Removed(key) => {
+                            process_ledger_key(&env, ctx, key, changes, &sales)
                         }
-                        LedgerEntryChange::Removed(key) => process_ledger_key(&env, key),
                         _ => {}
                     }
                 }
@@ -202,8 +313,10 @@ fn process_invoke_host_function_op(
 
 fn process_ledger_entry_data(
     env: &EnvClient,
+    ctx: &TransactionContext,
     data: &LedgerEntryData,
-    changes: Option<&LedgerEntryChanges>,
+    changes: &LedgerEntryChanges,
+    sales: &Vec<ZephyrSale>,
 ) {
     match data {
         LedgerEntryData::ContractData(SorobanContractDataEntry { key, val, .. }) => {
@@ -238,52 +351,140 @@ fn process_ledger_entry_data(
                         }
                     }
                     StorageKey::Glyph(hash) => {
+                        let expected_hash = hash.to_array();
+                        // The owner is written in the same call which finishes the mint
+                        let owner =
+                            get_updated_val(&env, changes, &StorageKey::GlyphOwner(hash.clone()));
                         let hash = env.to_scval(hash.clone());
                         let glyph: Glyph = env.from_scval(val);
                         let colors = env.to_scval(glyph.colors);
+                        // Everything derived from the colors works off this one decode
+                        let glyph_pixels =
+                            pixels::decode_pixels(glyph.width, glyph.length, &colors);
+                        let palette = pixels::get_palette(&glyph_pixels);
+
+                        render::cache_glyph_renders(&env, &hash, glyph.width, &glyph_pixels);
+                        index_glyph_colors(&env, &hash, &palette, glyph_pixels.len());
 
                         let existing = &env
                             .read_filter()
                             .column_equal_to_xdr("hash", &hash)
-                            .read::<ZephyrGlyphEmpty>()
+                            .read::<ZephyrGlyphMint>()
                             .unwrap();
+                        let minted = owner.is_some();
+                        /* `verified` is frozen once the mint completes, as later scrapes rewrite the colors
+                            without the hash changing. A glyph still being painted isn't expected to match yet.
+                            A mismatch is kept as `verified` false rather than stopping ingestion,
+                            it means the colors were decoded wrong or the contract changed how it hashes glyphs.
+                        */
+                        let mint = match existing.first() {
+                            Some(existing)
+                                if existing.mint_state == MintState::Minted.to_scval() =>
+                            {
+                                existing.clone()
+                            }
+                            existing => ZephyrGlyphMint {
+                                mint_state: match minted {
+                                    true => MintState::Minted,
+                                    false => MintState::Minting,
+                                }
+                                .to_scval(),
+                                mint_steps: existing.map_or(0, |existing| existing.mint_steps) + 1,
+                                minted_ledger: match minted {
+                                    true => ctx.ledger,
+                                    false => 0,
+                                },
+                                verified: match minted {
+                                    true => ScVal::Bool(
+                                        pixels::glyph_hash(glyph.width, &glyph_pixels)
+                                            == expected_hash,
+                                    ),
+                                    false => ScVal::Void,
+                                },
+                            },
+                        };
+                        let painted = ZephyrGlyphPainted {
+                            width: glyph.width,
+                            length: glyph.length,
+                            colors,
+                            color_count: palette.len() as u32,
+                            dominant_colors: dominant_colors_to_scval(&palette),
+                            transparent_bps: pixels::get_transparent_bps(&glyph_pixels),
+                            phash: ScVal::U64(pixels::perceptual_hash(glyph.width, &glyph_pixels)),
+                            verified: mint.verified,
+                            mint_state: mint.mint_state,
+                            mint_steps: mint.mint_steps,
+                            minted_ledger: mint.minted_ledger,
+                        };
 
                         if existing.len() == 0 {
                             env.put(&ZephyrGlyph {
                                 hash,
-                                owner: ScVal::Void,
+                                owner: owner.unwrap_or(ScVal::Void),
                                 minter: ScVal::Void,
-                                width: glyph.width,
-                                length: glyph.length,
-                                colors,
+                                width: painted.width,
+                                length: painted.length,
+                                colors: painted.colors,
+                                color_count: painted.color_count,
+                                dominant_colors: painted.dominant_colors,
+                                transparent_bps: painted.transparent_bps,
+                                phash: painted.phash,
+                                verified: painted.verified,
+                                mint_state: painted.mint_state,
+                                mint_steps: painted.mint_steps,
+                                minted_ledger: painted.minted_ledger,
                             });
                         } else {
-                            let glyph = ZephyrGlyphWidthLengthColors {
-                                width: glyph.width,
-                                length: glyph.length,
-                                colors,
-                            };
-
                             env.update()
                                 .column_equal_to_xdr("hash", &hash)
-                                .execute(&glyph)
+                                .execute(&painted)
                                 .unwrap();
                         }
                     }
                     StorageKey::GlyphOwner(hash) => {
+                        // When the final paint is in this call too the Glyph branch finishes the mint
+                        let painted =
+                            get_updated_val(&env, changes, &StorageKey::Glyph(hash.clone()));
+                        let expected_hash = hash.to_array();
                         let hash = env.to_scval(hash.clone());
                         let existing = &env
                             .read_filter()
                             .column_equal_to_xdr("hash", &hash)
-                            .read::<ZephyrGlyphEmpty>()
+                            .read::<ZephyrGlyph>()
                             .unwrap();
 
-                        if existing.len() > 0 {
+                        if let Some(existing) = existing.first() {
                             env.update()
                                 .column_equal_to_xdr("hash", &hash)
                                 .execute(&ZephyrGlyphOwner { owner: val.clone() })
                                 .unwrap();
+
+                            // The first owner marks the end of the mint
+                            if existing.mint_state == MintState::Minting.to_scval()
+                                && painted.is_none()
+                            {
+                                let glyph_pixels = pixels::decode_pixels(
+                                    existing.width,
+                                    existing.length,
+                                    &existing.colors,
+                                );
+
+                                env.update()
+                                    .column_equal_to_xdr("hash", &hash)
+                                    .execute(&ZephyrGlyphMint {
+                                        mint_state: MintState::Minted.to_scval(),
+                                        mint_steps: existing.mint_steps,
+                                        minted_ledger: ctx.ledger,
+                                        verified: ScVal::Bool(
+                                            pixels::glyph_hash(existing.width, &glyph_pixels)
+                                                == expected_hash,
+                                        ),
+                                    })
+                                    .unwrap();
+                            }
                         }
+
+                        validate_glyph_offers(&env, &hash, val);
                     }
                     StorageKey::GlyphMinter(hash) => {
                         let hash = env.to_scval(hash.clone());
@@ -306,116 +507,34 @@ fn process_ledger_entry_data(
                         let offers: SorobanVec<Offer> = env.from_scval(val);
                         let diff_offers =
                             get_diff_offers(&env, &key, changes, &Offers::Offers(offers.clone()));
-                        let owner = &env.read_contract_entry_by_scvalkey(
-                            CONTRACT_ADDRESS,
-                            env.to_scval(StorageKey::GlyphOwner(hash.clone())),
-                        );
+                        let selling = env.to_scval(hash.clone());
 
-                        if owner.is_ok() && owner.clone().unwrap().is_some() {
-                            let ContractDataEntry { entry, .. } = owner.clone().unwrap().unwrap();
-                            let LedgerEntry { data, .. } = entry;
+                        // Add or update
+                        if let Some(owner) = get_glyph_owner_after(&env, changes, hash) {
+                            for offer in offers.iter() {
+                                let (buying, amount) = glyph_offer_to_scvals(&env, offer);
+
+                                post_offer(
+                                    &env,
+                                    ctx,
+                                    new_offer(owner.clone(), selling.clone(), buying, amount),
+                                );
+                            }
+                        }
 
-                            if let LedgerEntryData::ContractData(SorobanContractDataEntry {
-                                val: owner,
-                                ..
-                            }) = data
-                            {
-                                // Add or update
+                        // Remove if exists, those were posted by whoever owned the glyph before
+                        if let Some(Offers::Offers(offers)) = diff_offers {
+                            if let Some(owner) = get_glyph_owner_before(&env, changes, hash) {
                                 for offer in offers.iter() {
-                                    let seller: ScVal = owner.clone();
-                                    let selling: ScVal = env.to_scval(hash.clone());
-                                    let buying: ScVal;
-                                    let amount: ScVal;
-
-                                    match offer {
-                                        // Selling a glyph for a glyph
-                                        Offer::Glyph(buying_hash) => {
-                                            buying = env.to_scval(buying_hash);
-                                            amount = ScVal::Void;
-                                        }
-                                        // Selling a glyph for an asset
-                                        Offer::Asset(sac, a) => {
-                                            buying = env.to_scval(sac);
-                                            amount = ScVal::I128(
-                                                // The amount of the buying asset the seller wants
-                                                Int128Parts {
-                                                    hi: (a >> 64) as i64,
-                                                    lo: a as u64,
-                                                },
-                                            );
-                                        }
-                                        _ => {
-                                            panic!("Invalid offer type")
-                                        }
-                                    }
-
-                                    // update if exists, otherwise put
-                                    let existing = env
-                                        .read_filter()
-                                        .column_equal_to_xdr("seller", &seller)
-                                        .column_equal_to_xdr("selling", &selling)
-                                        .column_equal_to_xdr("buying", &buying)
-                                        .column_equal_to_xdr("amount", &amount)
-                                        .read::<ZephyrOfferEmpty>()
-                                        .unwrap();
-
-                                    if existing.len() == 0 {
-                                        env.put(&ZephyrOffer {
-                                            seller,
-                                            selling,
-                                            buying,
-                                            amount,
-                                            active: ScVal::Bool(true),
-                                        });
-                                    } else {
-                                        env.update()
-                                            .column_equal_to_xdr("seller", &seller)
-                                            .column_equal_to_xdr("selling", &selling)
-                                            .column_equal_to_xdr("buying", &buying)
-                                            .column_equal_to_xdr("amount", &amount)
-                                            .execute(&ZephyrOfferActive {
-                                                active: ScVal::Bool(true),
-                                            })
-                                            .unwrap();
-                                    }
-                                }
-
-                                // Remove if exists
-                                if let Some(offers) = diff_offers {
-                                    if let Offers::Offers(offers) = offers {
-                                        for offer in offers.iter() {
-                                            let buying: ScVal;
-                                            let amount: ScVal;
-
-                                            match offer {
-                                                Offer::Glyph(buying_hash) => {
-                                                    buying = env.to_scval(buying_hash);
-                                                    amount = ScVal::Void;
-                                                }
-                                                Offer::Asset(sac, a) => {
-                                                    buying = env.to_scval(sac); // The asset the seller wants
-                                                    amount = ScVal::I128(
-                                                        // The amount of the buying asset the seller wants
-                                                        Int128Parts {
-                                                            hi: (a >> 64) as i64,
-                                                            lo: a as u64,
-                                                        },
-                                                    );
-                                                }
-                                                _ => {
-                                                    panic!("Invalid offer type")
-                                                }
-                                            }
-
-                                            env.update()
-                                                .column_equal_to_xdr("seller", &owner.clone())
-                                                .column_equal_to_xdr("selling", &env.to_scval(hash.clone()))
-                                                .column_equal_to_xdr("buying", &buying)
-                                                .column_equal_to_xdr("amount", &amount)
-                                                .execute(&ZephyrOfferActive { active: ScVal::Bool(false) })
-                                                .unwrap();
-                                        }
-                                    }
+                                    let (buying, amount) = glyph_offer_to_scvals(&env, offer);
+
+                                    close_offer(
+                                        &env,
+                                        ctx,
+                                        changes,
+                                        sales,
+                                        new_offer(owner.clone(), selling.clone(), buying, amount),
+                                    );
                                 }
                             }
                         }
@@ -429,64 +548,41 @@ fn process_ledger_entry_data(
                             changes,
                             &Offers::Addresses(offers.clone()),
                         );
+                        let selling = env.to_scval(sac.clone());
+                        let buying = env.to_scval(hash.clone());
+                        let amount = i128_to_scval(*amount);
+
+                        let owner = get_glyph_owner_after(&env, changes, hash);
 
                         // Add or update
-                        for owner in offers.iter() {
-                            let seller = env.to_scval(owner);
-                            let selling = env.to_scval(sac.clone());
-                            let buying = env.to_scval(hash.clone());
-                            let amount = ScVal::I128(Int128Parts {
-                                hi: (amount >> 64) as i64,
-                                lo: *amount as u64,
-                            });
+                        for bidder in offers.iter() {
+                            let bidder = env.to_scval(bidder);
 
-                            let existing = env
-                                .read_filter()
-                                .column_equal_to_xdr("seller", &seller)
-                                .column_equal_to_xdr("selling", &selling)
-                                .column_equal_to_xdr("buying", &buying)
-                                .column_equal_to_xdr("amount", &amount)
-                                .read::<ZephyrOfferEmpty>()
-                                .unwrap();
+                            balances::track_bidder(&env, ctx, changes, &bidder, &selling);
 
-                            if existing.len() == 0 {
-                                env.put(&ZephyrOffer {
-                                    seller,
-                                    selling,
-                                    buying,
-                                    amount,
-                                    active: ScVal::Bool(true),
-                                });
-                            } else {
-                                env.update()
-                                    .column_equal_to_xdr("seller", &seller)
-                                    .column_equal_to_xdr("selling", &selling)
-                                    .column_equal_to_xdr("buying", &buying)
-                                    .column_equal_to_xdr("amount", &amount)
-                                    .execute(&ZephyrOfferActive {
-                                        active: ScVal::Bool(true),
-                                    })
-                                    .unwrap();
-                            }
+                            let mut offer =
+                                new_offer(bidder, selling.clone(), buying.clone(), amount.clone());
+
+                            offer.validity = get_bid_validity(&env, &offer, owner.as_ref());
+
+                            post_offer(&env, ctx, offer);
                         }
 
                         // Remove if exists
-                        if let Some(offers) = diff_offers {
-                            if let Offers::Addresses(offers) = offers {
-                                for owner in offers.iter() {
-                                    let amount = ScVal::I128(Int128Parts {
-                                        hi: (amount >> 64) as i64,
-                                        lo: *amount as u64,
-                                    });
-
-                                    env.update()
-                                        .column_equal_to_xdr("seller", &env.to_scval(owner))
-                                        .column_equal_to_xdr("selling", &env.to_scval(sac.clone()))
-                                        .column_equal_to_xdr("buying", &env.to_scval(hash.clone()))
-                                        .column_equal_to_xdr("amount", &amount)
-                                        .execute(&ZephyrOfferActive { active: ScVal::Bool(false) })
-                                        .unwrap();
-                                }
+                        if let Some(Offers::Addresses(offers)) = diff_offers {
+                            for owner in offers.iter() {
+                                close_offer(
+                                    &env,
+                                    ctx,
+                                    changes,
+                                    sales,
+                                    new_offer(
+                                        env.to_scval(owner),
+                                        selling.clone(),
+                                        buying.clone(),
+                                        amount.clone(),
+                                    ),
+                                );
                             }
                         }
                     }
@@ -498,46 +594,288 @@ fn process_ledger_entry_data(
     }
 }
 
+// Only the top few colors are kept as dominant, the rest just count towards `color_count`
+const DOMINANT_COLORS: usize = 5;
+
+fn dominant_colors_to_scval(palette: &[(u32, u32)]) -> ScVal {
+    let dominant_colors = palette
+        .iter()
+        .take(DOMINANT_COLORS)
+        .map(|(color, count)| {
+            ScVal::Vec(Some(ScVec(
+                vec![ScVal::U32(*color), ScVal::U32(*count)]
+                    .try_into()
+                    .unwrap(),
+            )))
+        })
+        .collect::<Vec<ScVal>>();
+
+    ScVal::Vec(Some(ScVec(dominant_colors.try_into().unwrap())))
+}
+
+/* Color to glyph lookups for search, one row per color a glyph uses.
+    Colors painted over in a later update are zeroed out rather than left behind.
+*/
+fn index_glyph_colors(env: &EnvClient, hash: &ScVal, palette: &[(u32, u32)], total: usize) {
+    let total = total.max(1) as u64;
+
+    let existing = env
+        .read_filter()
+        .column_equal_to_xdr("hash", hash)
+        .read::<ZephyrGlyphColor>()
+        .unwrap();
+
+    for row in existing.iter() {
+        if row.pixels > 0 && !palette.iter().any(|(color, _)| *color == row.color) {
+            env.update()
+                .column_equal_to_xdr("hash", hash)
+                .column_equal_to("color", row.color)
+                .execute(&ZephyrGlyphColorCoverage {
+                    pixels: 0,
+                    coverage_bps: 0,
+                })
+                .unwrap();
+        }
+    }
+
+    for (color, count) in palette.iter() {
+        let coverage = ZephyrGlyphColorCoverage {
+            pixels: *count,
+            coverage_bps: (*count as u64 * 10_000 / total) as u32,
+        };
+
+        if existing.iter().any(|row| row.color == *color) {
+            env.update()
+                .column_equal_to_xdr("hash", hash)
+                .column_equal_to("color", *color)
+                .execute(&coverage)
+                .unwrap();
+        } else {
+            env.put(&ZephyrGlyphColor {
+                hash: hash.clone(),
+                color: *color,
+                pixels: coverage.pixels,
+                coverage_bps: coverage.coverage_bps,
+            });
+        }
+    }
+}
+
+fn get_dominant_colors(dominant_colors: &ScVal) -> Vec<u32> {
+    match dominant_colors {
+        ScVal::Vec(Some(ScVec(colors))) => colors
+            .iter()
+            .filter_map(|color| match color {
+                ScVal::Vec(Some(ScVec(pair))) => match pair.first() {
+                    Some(ScVal::U32(color)) => Some(*color),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
 fn get_diff_offers(
     env: &EnvClient,
     key: &StorageKey,
-    changes: Option<&LedgerEntryChanges>,
+    changes: &LedgerEntryChanges,
     offers: &Offers,
 ) -> Option<Offers> {
-    if changes.is_none() {
-        return None;
+    let val = get_state_val(&env, changes, key)?;
+
+    match offers {
+        Offers::Offers(offers) => {
+            let mut change_offers = SorobanVec::new(env.soroban());
+
+            for offer in env.from_scval::<SorobanVec<Offer>>(&val).iter() {
+                if !offers.contains(offer.clone()) {
+                    change_offers.push_back(offer);
+                }
+            }
+
+            Some(Offers::Offers(change_offers))
+        }
+        Offers::Addresses(offers) => {
+            let mut change_offers = SorobanVec::new(env.soroban());
+
+            for offer in env.from_scval::<SorobanVec<Address>>(&val).iter() {
+                if !offers.contains(offer.clone()) {
+                    change_offers.push_back(offer);
+                }
+            }
+
+            Some(Offers::Addresses(change_offers))
+        }
     }
+}
 
-    for change in changes.unwrap().iter() {
+// The value an entry held before the transaction touched it
+fn get_state_val(env: &EnvClient, changes: &LedgerEntryChanges, key: &StorageKey) -> Option<ScVal> {
+    for change in changes.iter() {
         if let LedgerEntryChange::State(LedgerEntry { data, .. }) = change {
             if let LedgerEntryData::ContractData(SorobanContractDataEntry { key: k, val, .. }) =
                 data
             {
                 if let Ok(k) = env.try_from_scval::<StorageKey>(k) {
                     if &k == key {
-                        match offers {
-                            Offers::Offers(offers) => {
-                                let mut change_offers = SorobanVec::new(env.soroban());
+                        return Some(val.clone());
+                    }
+                }
+            }
+        }
+    }
 
-                                for offer in env.from_scval::<SorobanVec<Offer>>(val).iter() {
-                                    if !offers.contains(offer.clone()) {
-                                        change_offers.push_back(offer);
-                                    }
-                                }
+    None
+}
 
-                                return Some(Offers::Offers(change_offers));
-                            }
-                            Offers::Addresses(offers) => {
-                                let mut change_offers = SorobanVec::new(env.soroban());
+// The value an entry was created or updated with by the transaction
+fn get_updated_val(
+    env: &EnvClient,
+    changes: &LedgerEntryChanges,
+    key: &StorageKey,
+) -> Option<ScVal> {
+    for change in changes.iter() {
+        match change {
+            LedgerEntryChange::Created(LedgerEntry { data, .. })
+            | LedgerEntryChange::Updated(LedgerEntry { data, .. }) => {
+                if let LedgerEntryData::ContractData(SorobanContractDataEntry {
+                    key: k, val, ..
+                }) = data
+                {
+                    if let Ok(k) = env.try_from_scval::<StorageKey>(k) {
+                        if &k == key {
+                            return Some(val.clone());
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
 
-                                for offer in env.from_scval::<SorobanVec<Address>>(val).iter() {
-                                    if !offers.contains(offer.clone()) {
-                                        change_offers.push_back(offer);
-                                    }
-                                }
+    None
+}
 
-                                return Some(Offers::Addresses(change_offers));
-                            }
+/* Owners come from the transaction's own GlyphOwner entries rather than current chain state,
+    which is wrong during backfill or when the glyph changes hands in the same transaction.
+    The entry only shows up if the transaction wrote it, otherwise the indexed owner is current.
+*/
+fn get_glyph_owner_before(
+    env: &EnvClient,
+    changes: &LedgerEntryChanges,
+    hash: &BytesN<32>,
+) -> Option<ScVal> {
+    get_state_val(&env, changes, &StorageKey::GlyphOwner(hash.clone()))
+        .or_else(|| get_indexed_glyph_owner(&env, &env.to_scval(hash.clone())))
+}
+
+fn get_glyph_owner_after(
+    env: &EnvClient,
+    changes: &LedgerEntryChanges,
+    hash: &BytesN<32>,
+) -> Option<ScVal> {
+    get_updated_val(&env, changes, &StorageKey::GlyphOwner(hash.clone()))
+        .or_else(|| get_indexed_glyph_owner(&env, &env.to_scval(hash.clone())))
+}
+
+fn get_indexed_glyph_owner(env: &EnvClient, hash: &ScVal) -> Option<ScVal> {
+    let glyphs = env
+        .read_filter()
+        .column_equal_to_xdr("hash", hash)
+        .read::<ZephyrGlyphOwner>()
+        .unwrap();
+
+    // Glyphs are indexed before their owner is known
+    glyphs
+        .first()
+        .map(|glyph| glyph.owner.clone())
+        .filter(|owner| *owner != ScVal::Void)
+}
+
+// All the offers a transaction dropped, either by updating or removing the entry
+fn get_removed_offers(env: &EnvClient, changes: &LedgerEntryChanges) -> Vec<RemovedOffer> {
+    let mut removed_offers = Vec::new();
+
+    for change in changes.iter() {
+        match change {
+            LedgerEntryChange::Updated(LedgerEntry {
+                data: LedgerEntryData::ContractData(SorobanContractDataEntry { key, val, .. }),
+                ..
+            }) => {
+                if let Ok(key) = env.try_from_scval::<StorageKey>(key) {
+                    let offers = match &key {
+                        StorageKey::GlyphOffer(_) => Offers::Offers(env.from_scval(val)),
+                        StorageKey::AssetOffer(..) => Offers::Addresses(env.from_scval(val)),
+                        _ => continue,
+                    };
+
+                    if let Some(offers) = get_diff_offers(&env, &key, changes, &offers) {
+                        removed_offers.extend(to_removed_offer(&env, &key, offers));
+                    }
+                }
+            }
+            LedgerEntryChange::Removed(LedgerKey::ContractData(LedgerKeyContractData {
+                key,
+                ..
+            })) => {
+                if let Ok(key) = env.try_from_scval::<StorageKey>(key) {
+                    if let Some(val) = get_state_val(&env, changes, &key) {
+                        let offers = match &key {
+                            StorageKey::GlyphOffer(_) => Offers::Offers(env.from_scval(&val)),
+                            StorageKey::AssetOffer(..) => Offers::Addresses(env.from_scval(&val)),
+                            _ => continue,
+                        };
+
+                        removed_offers.extend(to_removed_offer(&env, &key, offers));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    removed_offers
+}
+
+fn to_removed_offer(env: &EnvClient, key: &StorageKey, offers: Offers) -> Option<RemovedOffer> {
+    match (key, offers) {
+        (StorageKey::AssetOffer(hash, sac, amount), Offers::Addresses(bidders)) => {
+            Some(RemovedOffer::Bids {
+                glyph: env.to_scval(hash.clone()),
+                asset: env.to_scval(sac.clone()),
+                amount: i128_to_scval(*amount),
+                bidders: bidders.iter().map(|bidder| env.to_scval(bidder)).collect(),
+            })
+        }
+        (StorageKey::GlyphOffer(hash), Offers::Offers(offers)) => Some(RemovedOffer::Asks {
+            glyph: env.to_scval(hash.clone()),
+            asks: offers
+                .iter()
+                .filter(|offer| matches!(offer, Offer::Glyph(_) | Offer::Asset(..)))
+                .map(|offer| glyph_offer_to_scvals(&env, offer))
+                .collect(),
+        }),
+        _ => None,
+    }
+}
+
+// Glyphs (hash, previous owner, new owner) which changed hands in the transaction
+fn get_owner_changes(env: &EnvClient, changes: &LedgerEntryChanges) -> Vec<(ScVal, ScVal, ScVal)> {
+    let mut owner_changes = Vec::new();
+
+    for change in changes.iter() {
+        if let LedgerEntryChange::Updated(LedgerEntry {
+            data: LedgerEntryData::ContractData(SorobanContractDataEntry { key, val, .. }),
+            ..
+        }) = change
+        {
+            if let Ok(key) = env.try_from_scval::<StorageKey>(key) {
+                if let StorageKey::GlyphOwner(hash) = &key {
+                    if let Some(previous) = get_state_val(&env, changes, &key) {
+                        if previous != *val {
+                            owner_changes.push((env.to_scval(hash.clone()), previous, val.clone()));
                         }
                     }
                 }
@@ -545,10 +883,76 @@ fn get_diff_offers(
         }
     }
 
-    None
+    owner_changes
+}
+
+/* A fill is an offer going away in the same transaction the glyph changes hands.
+    Anything else dropping an offer is a cancellation and isn't recorded here.
+*/
+fn process_sales(
+    env: &EnvClient,
+    ctx: &TransactionContext,
+    args: &VecM<ScVal>,
+    changes: &LedgerEntryChanges,
+) -> Vec<ZephyrSale> {
+    let removed_offers = get_removed_offers(&env, changes);
+    let owner_changes = get_owner_changes(&env, changes);
+    // Asks the call was given, a buyer has to name the one they're filling
+    let sent_offers: Vec<(ScVal, ScVal)> = args
+        .iter()
+        .filter_map(|arg| match env.try_from_scval::<Offer>(arg) {
+            Ok(Offer::Asset(sac, amount)) => Some((env.to_scval(sac), i128_to_scval(amount))),
+            _ => None,
+        })
+        .collect();
+    let mut sales = Vec::new();
+
+    for (glyph, seller, buyer) in owner_changes.iter() {
+        if let Some((asset, amount)) = sales::find_filled_offer(
+            &removed_offers,
+            &sent_offers,
+            &owner_changes,
+            glyph,
+            seller,
+            buyer,
+        ) {
+            let sale = ZephyrSale {
+                glyph: glyph.clone(),
+                seller: seller.clone(),
+                buyer: buyer.clone(),
+                asset,
+                amount,
+                ledger: ctx.ledger,
+                timestamp: ScVal::U64(ctx.timestamp),
+                tx_hash: ctx.tx_hash.clone(),
+            };
+
+            // Replaying a transaction (e.g. a second backfill) mustn't count the sale twice
+            let existing = env
+                .read_filter()
+                .column_equal_to_xdr("glyph", &sale.glyph)
+                .column_equal_to_xdr("tx_hash", &sale.tx_hash)
+                .read::<ZephyrSaleEmpty>()
+                .unwrap();
+
+            if existing.len() == 0 {
+                env.put(&sale);
+            }
+
+            sales.push(sale);
+        }
+    }
+
+    sales
 }
 
-fn process_ledger_key(env: &EnvClient, key: &LedgerKey) {
+fn process_ledger_key(
+    env: &EnvClient,
+    ctx: &TransactionContext,
+    key: &LedgerKey,
+    changes: &LedgerEntryChanges,
+    sales: &Vec<ZephyrSale>,
+) {
     if let LedgerKey::ContractData(LedgerKeyContractData { key, .. }) = key {
         if let Ok(key) = env.try_from_scval::<StorageKey>(key) {
             match key {
@@ -558,64 +962,33 @@ fn process_ledger_key(env: &EnvClient, key: &LedgerKey) {
                 // StorageKey::GlyphMinter(hash),
                 StorageKey::GlyphOffer(hash) => {
                     let selling = env.to_scval(hash.clone());
-                    let owner = &env.read_contract_entry_by_scvalkey(
-                        CONTRACT_ADDRESS,
-                        env.to_scval(StorageKey::GlyphOwner(hash.clone())),
-                    );
 
-                    if owner.is_ok() && owner.clone().unwrap().is_some() {
-                        let ContractDataEntry { entry, .. } = owner.clone().unwrap().unwrap();
-                        let LedgerEntry { data, .. } = entry;
-
-                        if let LedgerEntryData::ContractData(SorobanContractDataEntry {
-                            val: owner,
-                            ..
-                        }) = data
-                        {
-                            let offers = env
-                                .read_filter()
-                                .column_equal_to_xdr("seller", &owner)
-                                .column_equal_to_xdr("selling", &selling)
-                                .read::<ZephyrOfferEmpty>()
-                                .unwrap();
+                    if let Some(owner) = get_glyph_owner_before(&env, changes, &hash) {
+                        let offers = env
+                            .read_filter()
+                            .column_equal_to_xdr("seller", &owner)
+                            .column_equal_to_xdr("selling", &selling)
+                            .column_equal_to_xdr("status", &OfferStatus::Active.to_scval())
+                            .read::<ZephyrOfferNoStatus>()
+                            .unwrap();
 
-                            for _ in offers {
-                                env.update()
-                                    .column_equal_to_xdr("seller", &owner)
-                                    .column_equal_to_xdr("selling", &selling)
-                                    .execute(&ZephyrOfferActive {
-                                        active: ScVal::Bool(false),
-                                    })
-                                    .unwrap();
-                            }
+                        for offer in offers {
+                            close_offer(&env, ctx, changes, sales, offer);
                         }
                     }
                 }
                 StorageKey::AssetOffer(hash, sac, amount) => {
-                    let selling = env.to_scval(hash.clone());
-                    let buying = env.to_scval(sac.clone());
-                    let amount = ScVal::I128(Int128Parts {
-                        hi: (amount >> 64) as i64,
-                        lo: amount as u64,
-                    });
-
                     let offers = env
                         .read_filter()
-                        .column_equal_to_xdr("selling", &selling)
-                        .column_equal_to_xdr("buying", &buying)
-                        .column_equal_to_xdr("amount", &amount)
-                        .read::<ZephyrOfferEmpty>()
+                        .column_equal_to_xdr("selling", &env.to_scval(sac.clone()))
+                        .column_equal_to_xdr("buying", &env.to_scval(hash.clone()))
+                        .column_equal_to_xdr("amount", &i128_to_scval(amount))
+                        .column_equal_to_xdr("status", &OfferStatus::Active.to_scval())
+                        .read::<ZephyrOfferNoStatus>()
                         .unwrap();
 
-                    for _ in offers {
-                        env.update()
-                            .column_equal_to_xdr("selling", &selling)
-                            .column_equal_to_xdr("buying", &buying)
-                            .column_equal_to_xdr("amount", &amount)
-                            .execute(&ZephyrOfferActive {
-                                active: ScVal::Bool(false),
-                            })
-                            .unwrap();
+                    for offer in offers {
+                        close_offer(&env, ctx, changes, sales, offer);
                     }
                 }
                 _ => {}
@@ -624,113 +997,465 @@ fn process_ledger_key(env: &EnvClient, key: &LedgerKey) {
     }
 }
 
-fn address_string_to_scval(env: &EnvClient, address: &String) -> ScVal {
-    let mut public_key = [0u8; 32];
-
-    let public_key_bytes =
-        Address::from_string_bytes(&Bytes::from_slice(&env.soroban(), address.as_bytes()));
-    let public_key_bytes = public_key_bytes.to_xdr(env.soroban());
-
-    public_key_bytes
-        .slice(public_key_bytes.len() - 32..)
-        .copy_into_slice(&mut public_key);
-
-    ScVal::Address(ScAddress::Account(AccountId(
-        PublicKey::PublicKeyTypeEd25519(Uint256(public_key)),
-    )))
+fn glyph_offer_to_scvals(env: &EnvClient, offer: Offer) -> (ScVal, ScVal) {
+    match offer {
+        // Selling a glyph for a glyph
+        Offer::Glyph(buying_hash) => (env.to_scval(buying_hash), ScVal::Void),
+        // Selling a glyph for an asset (sac) for a specific amount of it
+        Offer::Asset(sac, amount) => (env.to_scval(sac), i128_to_scval(amount)),
+        _ => {
+            panic!("Invalid offer type")
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct BackfillRequest {
-    envelope_xdr: String,
-    result_meta_xdr: String,
-    result_xdr: String,
+fn new_offer(seller: ScVal, selling: ScVal, buying: ScVal, amount: ScVal) -> ZephyrOfferNoStatus {
+    ZephyrOfferNoStatus {
+        id: offer_id(&seller, &selling, &buying, &amount),
+        seller,
+        selling,
+        buying,
+        amount,
+        validity: OfferValidity::Valid.to_scval(),
+    }
 }
 
-#[no_mangle]
-pub extern "C" fn backfill() {
-    let env = EnvClient::empty();
-    let request: BackfillRequest = env.read_request_body();
-
-    let transaction_envelope =
-        TransactionEnvelope::from_xdr_base64(request.envelope_xdr, Limits::none()).unwrap();
-    let transaction_result_meta = TransactionResultMeta {
-        result: TransactionResultPair {
-            transaction_hash: Hash([0; 32]),
-            result: TransactionResult::from_xdr_base64(request.result_xdr, Limits::none()).unwrap(),
-        },
-        fee_processing: LedgerEntryChanges(vec![].try_into().unwrap()),
-        tx_apply_processing: TransactionMeta::from_xdr_base64(
-            request.result_meta_xdr,
-            Limits::none(),
-        )
-        .unwrap(),
-    };
+/* The same (seller, selling, buying, amount) always maps to the same row,
+    so reposting a closed offer brings the original row back instead of duplicating it.
+*/
+fn offer_id(seller: &ScVal, selling: &ScVal, buying: &ScVal, amount: &ScVal) -> ScVal {
+    let mut hasher = Sha256::new();
 
-    process_transaction(&env, &transaction_envelope, &transaction_result_meta);
+    hasher.update(CONTRACT_ADDRESS);
 
-    env.conclude("OK");
-}
+    for val in [seller, selling, buying, amount] {
+        hasher.update(WriteXdr::to_xdr(val, Limits::none()).unwrap());
+    }
 
-#[derive(Serialize, Deserialize)]
-pub struct GetColorsRequest {
-    owner: String,
+    ScVal::Bytes(ScBytes(hasher.finalize().to_vec().try_into().unwrap()))
 }
 
-#[no_mangle]
-pub extern "C" fn get_colors() {
-    let env = EnvClient::empty();
-    let request: GetColorsRequest = env.read_request_body();
-    let owner = address_string_to_scval(&env, &request.owner);
+// Add a new offer or bring back one which was previously closed
+fn post_offer(env: &EnvClient, ctx: &TransactionContext, offer: ZephyrOfferNoStatus) {
+    if let Some(asset) = offer.asset() {
+        assets::index_asset(&env, asset);
+    }
 
-    let colors = env
+    let existing = env
         .read_filter()
-        .column_equal_to_xdr("owner", &owner)
-        .read::<ZephyrColor>()
+        .column_equal_to_xdr("id", &offer.id)
+        .read::<ZephyrOfferStatus>()
         .unwrap();
 
-    env.conclude(colors);
-}
+    match existing.first() {
+        None => env.put(&ZephyrOffer {
+            id: offer.id.clone(),
+            seller: offer.seller.clone(),
+            selling: offer.selling.clone(),
+            buying: offer.buying.clone(),
+            amount: offer.amount.clone(),
+            status: OfferStatus::Active.to_scval(),
+            validity: offer.validity.clone(),
+            posted_ledger: ctx.ledger,
+            closed_ledger: 0,
+        }),
+        // Every update to an entry rewrites all of its offers, only new ones are posted
+        Some(existing) if existing.status == OfferStatus::Active.to_scval() => return,
+        Some(_) => env
+            .update()
+            .column_equal_to_xdr("id", &offer.id)
+            .execute(&ZephyrOfferPosted {
+                status: OfferStatus::Active.to_scval(),
+                validity: offer.validity.clone(),
+                posted_ledger: ctx.ledger,
+                closed_ledger: 0,
+            })
+            .unwrap(),
+    }
 
-#[derive(Serialize, Deserialize)]
-pub struct GetGlyphsRequest {
-    owner: Option<String>,
+    matches::update_offer_matches(&env, &offer);
+    put_offer_event(&env, ctx, offer, OfferStatus::Active);
 }
 
-#[no_mangle]
-pub extern "C" fn get_glyphs() {
-    let env = EnvClient::empty();
-    let request: GetGlyphsRequest = env.read_request_body();
-
-    match request.owner {
+fn close_offer(
+    env: &EnvClient,
+    ctx: &TransactionContext,
+    changes: &LedgerEntryChanges,
+    sales: &Vec<ZephyrSale>,
+    offer: ZephyrOfferNoStatus,
+) {
+    let existing = env
+        .read_filter()
+        .column_equal_to_xdr("id", &offer.id)
+        .column_equal_to_xdr("status", &OfferStatus::Active.to_scval())
+        .read::<ZephyrOfferEmpty>()
+        .unwrap();
+
+    if existing.len() == 0 {
+        return;
+    }
+
+    let status = sales::get_close_reason(sales, &get_owner_changes(&env, changes), &offer);
+
+    env.update()
+        .column_equal_to_xdr("id", &offer.id)
+        .execute(&ZephyrOfferClosed {
+            status: status.to_scval(),
+            closed_ledger: ctx.ledger,
+        })
+        .unwrap();
+
+    matches::update_offer_matches(&env, &offer);
+    put_offer_event(&env, ctx, offer, status);
+}
+
+/* Asks stay active after the glyph has been transferred away,
+    and a bid is pointless once the bidder owns the glyph.
+    Neither can be filled, so they're flagged stale until the glyph comes back around.
+*/
+fn validate_glyph_offers(env: &EnvClient, hash: &ScVal, owner: &ScVal) {
+    let asks = env
+        .read_filter()
+        .column_equal_to_xdr("selling", hash)
+        .column_equal_to_xdr("status", &OfferStatus::Active.to_scval())
+        .read::<ZephyrOfferNoStatus>()
+        .unwrap();
+
+    for offer in asks {
+        let validity = if offer.seller == *owner {
+            OfferValidity::Valid
+        } else {
+            OfferValidity::Stale
+        };
+
+        set_offer_validity(&env, &offer, validity);
+    }
+
+    let bids = env
+        .read_filter()
+        .column_equal_to_xdr("buying", hash)
+        .column_equal_to_xdr("status", &OfferStatus::Active.to_scval())
+        .read::<ZephyrOfferNoStatus>()
+        .unwrap();
+
+    // Swaps targeting this glyph are asks on another glyph and validated with that one
+    for offer in bids.into_iter().filter(|offer| offer.amount != ScVal::Void) {
+        let validity = get_bid_validity(&env, &offer, Some(owner));
+
+        set_offer_validity(&env, &offer, validity);
+    }
+}
+
+// Re-check a bidder's bids in an asset after their balance of it changed
+fn validate_bidder_offers(env: &EnvClient, bidder: &ScVal, asset: &ScVal) {
+    let bids = env
+        .read_filter()
+        .column_equal_to_xdr("seller", bidder)
+        .column_equal_to_xdr("selling", asset)
+        .column_equal_to_xdr("status", &OfferStatus::Active.to_scval())
+        .read::<ZephyrOfferNoStatus>()
+        .unwrap();
+
+    for offer in bids {
+        let owner = get_indexed_glyph_owner(&env, &offer.buying);
+        let validity = get_bid_validity(&env, &offer, owner.as_ref());
+
+        set_offer_validity(&env, &offer, validity);
+    }
+}
+
+// A bid's funding is unknown until the bidder's balance entry has been seen
+fn get_bid_validity(
+    env: &EnvClient,
+    offer: &ZephyrOfferNoStatus,
+    owner: Option<&ScVal>,
+) -> OfferValidity {
+    if owner == Some(&offer.seller) {
+        return OfferValidity::Stale;
+    }
+
+    let balance = env
+        .read_filter()
+        .column_equal_to_xdr("address", &offer.seller)
+        .column_equal_to_xdr("asset", &offer.selling)
+        .read::<ZephyrBidderBalance>()
+        .unwrap()
+        .first()
+        .and_then(|bidder| scval_to_i128(&bidder.balance));
+
+    match (balance, scval_to_i128(&offer.amount)) {
+        (None, _) => OfferValidity::Unknown,
+        (Some(balance), Some(amount)) if balance < amount => OfferValidity::Unfunded,
+        _ => OfferValidity::Valid,
+    }
+}
+
+fn set_offer_validity(env: &EnvClient, offer: &ZephyrOfferNoStatus, validity: OfferValidity) {
+    let validity = validity.to_scval();
+
+    if offer.validity != validity {
+        env.update()
+            .column_equal_to_xdr("id", &offer.id)
+            .execute(&ZephyrOfferValidity { validity })
+            .unwrap();
+
+        matches::update_offer_matches(&env, offer);
+    }
+}
+
+fn put_offer_event(
+    env: &EnvClient,
+    ctx: &TransactionContext,
+    offer: ZephyrOfferNoStatus,
+    status: OfferStatus,
+) {
+    let status = status.to_scval();
+    let existing = env
+        .read_filter()
+        .column_equal_to_xdr("id", &offer.id)
+        .column_equal_to_xdr("status", &status)
+        .column_equal_to("ledger", ctx.ledger)
+        .read::<ZephyrOfferEventEmpty>()
+        .unwrap();
+
+    // Same as sales, a replayed transaction already has its events
+    if existing.len() > 0 {
+        return;
+    }
+
+    env.put(&ZephyrOfferEvent {
+        id: offer.id,
+        seller: offer.seller,
+        selling: offer.selling,
+        buying: offer.buying,
+        amount: offer.amount,
+        status,
+        ledger: ctx.ledger,
+        timestamp: ScVal::U64(ctx.timestamp),
+    });
+}
+
+fn address_string_to_scval(env: &EnvClient, address: &String) -> ScVal {
+    let mut public_key = [0u8; 32];
+
+    let public_key_bytes =
+        Address::from_string_bytes(&Bytes::from_slice(&env.soroban(), address.as_bytes()));
+    let public_key_bytes = public_key_bytes.to_xdr(env.soroban());
+
+    public_key_bytes
+        .slice(public_key_bytes.len() - 32..)
+        .copy_into_slice(&mut public_key);
+
+    // Contract addresses (e.g. SACs) are C..., everything else is an account
+    if address.starts_with('C') {
+        ScVal::Address(ScAddress::Contract(Hash(public_key)))
+    } else {
+        ScVal::Address(ScAddress::Account(AccountId(
+            PublicKey::PublicKeyTypeEd25519(Uint256(public_key)),
+        )))
+    }
+}
+
+// Back to a G... or C... string, None while unknown
+fn scval_to_address_string(env: &EnvClient, address: &ScVal) -> Option<String> {
+    if !matches!(address, ScVal::Address(_)) {
+        return None;
+    }
+
+    let address: Address = env.from_scval(address);
+    let address = address.to_string();
+    let mut bytes = vec![0u8; address.len() as usize];
+
+    address.copy_into_slice(&mut bytes);
+
+    Some(String::from_utf8(bytes).unwrap())
+}
+
+fn hash_string_to_scval(hash: &String) -> ScVal {
+    ScVal::Bytes(ScBytes(BytesM::from_str(hash.as_str()).unwrap()))
+}
+
+fn i128_to_scval(amount: i128) -> ScVal {
+    ScVal::I128(Int128Parts {
+        hi: (amount >> 64) as i64,
+        lo: amount as u64,
+    })
+}
+
+fn scval_to_i128(val: &ScVal) -> Option<i128> {
+    match val {
+        ScVal::I128(Int128Parts { hi, lo }) => Some(((*hi as i128) << 64) | *lo as i128),
+        _ => None,
+    }
+}
+
+fn scval_to_u64(val: &ScVal) -> Option<u64> {
+    match val {
+        ScVal::U64(val) => Some(*val),
+        _ => None,
+    }
+}
+
+#[derive(Serialize)]
+pub struct ErrorResponse {
+    error: String,
+}
+
+// Bad requests get an error back rather than aborting the function
+fn conclude_error(env: &EnvClient, error: &str) {
+    env.conclude(&ErrorResponse {
+        error: error.to_string(),
+    });
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BackfillRequest {
+    hash: String,
+    ledger: u32,
+    timestamp: u64,
+    envelope_xdr: String,
+    result_meta_xdr: String,
+    result_xdr: String,
+}
+
+#[no_mangle]
+pub extern "C" fn backfill() {
+    let env = EnvClient::empty();
+    let request: BackfillRequest = env.read_request_body();
+
+    let mut transaction_hash = [0; 32];
+    hex::decode_to_slice(&request.hash, &mut transaction_hash).unwrap();
+
+    let transaction_envelope =
+        TransactionEnvelope::from_xdr_base64(request.envelope_xdr, Limits::none()).unwrap();
+    let transaction_result_meta = TransactionResultMeta {
+        result: TransactionResultPair {
+            transaction_hash: Hash(transaction_hash),
+            result: TransactionResult::from_xdr_base64(request.result_xdr, Limits::none()).unwrap(),
+        },
+        fee_processing: LedgerEntryChanges(vec![].try_into().unwrap()),
+        tx_apply_processing: TransactionMeta::from_xdr_base64(
+            request.result_meta_xdr,
+            Limits::none(),
+        )
+        .unwrap(),
+    };
+
+    put_latest_ledger(&env, request.ledger, request.timestamp);
+
+    process_transaction(
+        &env,
+        request.ledger,
+        request.timestamp,
+        &balances::read_bidder_addresses(&env),
+        &transaction_envelope,
+        &transaction_result_meta,
+    );
+
+    env.conclude("OK");
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetColorsRequest {
+    owner: String,
+}
+
+#[no_mangle]
+pub extern "C" fn get_colors() {
+    let env = EnvClient::empty();
+    let request: GetColorsRequest = env.read_request_body();
+    let owner = address_string_to_scval(&env, &request.owner);
+
+    let colors = env
+        .read_filter()
+        .column_equal_to_xdr("owner", &owner)
+        .read::<ZephyrColor>()
+        .unwrap();
+
+    env.conclude(colors);
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetGlyphsRequest {
+    owner: Option<String>,
+    state: Option<MintState>,
+    min_colors: Option<u32>,
+    max_colors: Option<u32>,
+    dominant_color: Option<String>, // hex, e.g. "#ff0000"
+    min_transparent_bps: Option<u32>,
+    max_transparent_bps: Option<u32>,
+    verified: Option<bool>, // false lists minted glyphs whose hash didn't match their colors
+}
+
+#[no_mangle]
+pub extern "C" fn get_glyphs() {
+    let env = EnvClient::empty();
+    let request: GetGlyphsRequest = env.read_request_body();
+    let dominant_color = match request.dominant_color {
+        Some(color) => match pixels::hex_to_color(&color) {
+            Some(color) => Some(color),
+            None => return conclude_error(&env, "Invalid dominant color"),
+        },
+        None => None,
+    };
+
+    let mut glyphs = match request.owner {
         Some(owner) => {
             let owner = address_string_to_scval(&env, &owner);
-            let glyphs = env
-                .read_filter()
+
+            env.read_filter()
                 .column_equal_to_xdr("owner", &owner)
                 .read::<ZephyrGlyphNoColors>()
-                .unwrap();
-
-            env.conclude(&glyphs);
+                .unwrap()
         }
-        None => {
-            let glyphs = env.read::<ZephyrGlyphNoColors>();
+        None => env.read::<ZephyrGlyphNoColors>(),
+    };
 
-            env.conclude(&glyphs);
-        }
-    }
+    glyphs.retain(|glyph| {
+        request
+            .state
+            .map_or(true, |state| glyph.mint_state == state.to_scval())
+            && request
+                .min_colors
+                .map_or(true, |min| glyph.color_count >= min)
+            && request
+                .max_colors
+                .map_or(true, |max| glyph.color_count <= max)
+            && request
+                .min_transparent_bps
+                .map_or(true, |min| glyph.transparent_bps >= min)
+            && request
+                .max_transparent_bps
+                .map_or(true, |max| glyph.transparent_bps <= max)
+            && request
+                .verified
+                .map_or(true, |verified| glyph.verified == ScVal::Bool(verified))
+            && dominant_color.map_or(true, |color| {
+                get_dominant_colors(&glyph.dominant_colors).contains(&color)
+            })
+    });
+
+    env.conclude(&glyphs);
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct GetGlyphRequest {
     hash: String,
+    decode: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct DecodedGlyph {
+    #[serde(flatten)]
+    glyph: ZephyrGlyph,
+    decoded: pixels::DecodedColors,
 }
 
 #[no_mangle]
 pub extern "C" fn get_glyph() {
     let env = EnvClient::empty();
     let request: GetGlyphRequest = env.read_request_body();
-    let hash = ScVal::Bytes(ScBytes(BytesM::from_str(request.hash.as_str()).unwrap()));
+    let hash = hash_string_to_scval(&request.hash);
 
     let glyphs = env
         .read_filter()
@@ -738,7 +1463,396 @@ pub extern "C" fn get_glyph() {
         .read::<ZephyrGlyph>()
         .unwrap();
 
-    env.conclude(&glyphs);
+    if request.decode.unwrap_or(false) {
+        let glyphs: Vec<DecodedGlyph> = glyphs
+            .into_iter()
+            .map(|glyph| DecodedGlyph {
+                decoded: pixels::decode_glyph(glyph.width, glyph.length, &glyph.colors),
+                glyph,
+            })
+            .collect();
+
+        env.conclude(&glyphs);
+    } else {
+        env.conclude(&glyphs);
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RenderGlyphRequest {
+    hash: String,
+    scale: Option<u32>,         // output pixels per glyph pixel
+    background: Option<String>, // hex color, transparent if left out
+}
+
+#[no_mangle]
+pub extern "C" fn render_glyph() {
+    let env = EnvClient::empty();
+    let request: RenderGlyphRequest = env.read_request_body();
+    let glyph = match read_glyph(&env, &request.hash) {
+        Some(glyph) => glyph,
+        None => return conclude_error(&env, "Glyph not found"),
+    };
+    let scale = request.scale.unwrap_or(1).clamp(1, 64);
+    let background = match request.background {
+        Some(background) => match render::parse_background(&background) {
+            Some(background) => Some(background),
+            None => return conclude_error(&env, "Invalid background"),
+        },
+        None => None,
+    };
+
+    // The cached render is at the natural size, `scale` only changes the svg's width and height
+    let svg = match (scale, &background) {
+        (1, None) => render::read_or_render_svg(&env, &glyph),
+        _ => render::render_svg(
+            glyph.width,
+            &pixels::decode_pixels(glyph.width, glyph.length, &glyph.colors),
+            scale,
+            background.as_deref(),
+        ),
+    };
+
+    env.conclude(&svg);
+}
+
+#[no_mangle]
+pub extern "C" fn render_glyph_png() {
+    let env = EnvClient::empty();
+    let request: RenderGlyphRequest = env.read_request_body();
+    let glyph = match read_glyph(&env, &request.hash) {
+        Some(glyph) => glyph,
+        None => return conclude_error(&env, "Glyph not found"),
+    };
+    let glyph_pixels = pixels::decode_pixels(glyph.width, glyph.length, &glyph.colors);
+    let height = glyph_pixels.len() as u32 / glyph.width.max(1);
+    let scale = request.scale.unwrap_or(1).max(1);
+    let background = match request.background {
+        Some(background) => match render::parse_background(&background) {
+            Some(background) => Some(background),
+            None => return conclude_error(&env, "Invalid background"),
+        },
+        None => None,
+    };
+
+    let cacheable =
+        background.is_none() && render::png_render_scales(glyph.width, height).contains(&scale);
+
+    let png = if cacheable {
+        render::read_or_render_png(&env, &glyph, scale).map(|png| render::encode_base64(&png))
+    } else {
+        render::render_png_base64(glyph.width, &glyph_pixels, scale, background.as_deref())
+    };
+
+    // Big glyphs at a big scale would blow through the module's memory, see `render::MAX_PNG_SIZE`
+    let png = match png {
+        Some(png) => png,
+        None => return conclude_error(&env, "Scale too large for this glyph"),
+    };
+
+    env.conclude(&png);
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SearchGlyphsByColorRequest {
+    colors: Vec<String>, // hex, e.g. "#ff0000"
+    tolerance: Option<f64>,
+    space: Option<pixels::ColorSpace>,
+    limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct ColorSearchResult {
+    hash: ScVal,
+    coverage_bps: u32, // share of the glyph covered by the matching colors
+    matched_colors: Vec<String>,
+}
+
+/* Glyphs using every one of the requested colors, or something within `tolerance` of each,
+    with those covering the most of the glyph first.
+*/
+#[no_mangle]
+pub extern "C" fn search_glyphs_by_color() {
+    let env = EnvClient::empty();
+    let request: SearchGlyphsByColorRequest = env.read_request_body();
+    let space = request.space.unwrap_or(pixels::ColorSpace::Rgb);
+    let tolerance = request.tolerance.unwrap_or(0.0);
+    let colors: Vec<u32> = match request
+        .colors
+        .iter()
+        .map(|color| pixels::hex_to_color(color))
+        .collect()
+    {
+        Some(colors) => colors,
+        None => return conclude_error(&env, "Invalid color"),
+    };
+
+    // Exact matches can go straight to the index, anything fuzzier has to look at every color
+    let rows: Vec<ZephyrGlyphColor> = if tolerance == 0.0 {
+        colors
+            .iter()
+            .flat_map(|color| {
+                env.read_filter()
+                    .column_equal_to("color", *color)
+                    .read::<ZephyrGlyphColor>()
+                    .unwrap()
+            })
+            .collect()
+    } else {
+        env.read::<ZephyrGlyphColor>()
+    };
+
+    let mut results: Vec<ColorSearchResult> = Vec::new();
+    let mut found: Vec<(ScVal, Vec<&ZephyrGlyphColor>)> = Vec::new();
+
+    for row in rows.iter().filter(|row| row.pixels > 0) {
+        let matching = colors
+            .iter()
+            .any(|color| pixels::color_distance(*color, row.color, space) <= tolerance);
+
+        if !matching {
+            continue;
+        }
+
+        match found.iter_mut().find(|(hash, _)| *hash == row.hash) {
+            Some((_, rows)) => {
+                if !rows.iter().any(|existing| existing.color == row.color) {
+                    rows.push(row)
+                }
+            }
+            None => found.push((row.hash.clone(), vec![row])),
+        }
+    }
+
+    for (hash, rows) in found {
+        let has_every_color = colors.iter().all(|color| {
+            rows.iter()
+                .any(|row| pixels::color_distance(*color, row.color, space) <= tolerance)
+        });
+
+        if has_every_color {
+            results.push(ColorSearchResult {
+                hash,
+                coverage_bps: rows.iter().map(|row| row.coverage_bps).sum(),
+                matched_colors: rows
+                    .iter()
+                    .map(|row| pixels::color_to_hex(row.color))
+                    .collect(),
+            });
+        }
+    }
+
+    results.sort_by_key(|result| core::cmp::Reverse(result.coverage_bps));
+    results.truncate(request.limit.unwrap_or(100) as usize);
+
+    env.conclude(&results);
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FindSimilarGlyphsRequest {
+    hash: String,
+    max_distance: Option<u32>, // differing bits out of 64
+    limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct SimilarGlyph {
+    #[serde(flatten)]
+    glyph: ZephyrGlyphNoColors,
+    distance: u32,
+    duplicate: bool,
+}
+
+// Perceptual hashes this close are as good as the same picture
+const DUPLICATE_DISTANCE: u32 = 2;
+
+#[no_mangle]
+pub extern "C" fn find_similar_glyphs() {
+    let env = EnvClient::empty();
+    let request: FindSimilarGlyphsRequest = env.read_request_body();
+    let hash = hash_string_to_scval(&request.hash);
+    let max_distance = request.max_distance.unwrap_or(10);
+
+    let glyphs = env.read::<ZephyrGlyphNoColors>();
+    let phash = match glyphs.iter().find(|glyph| glyph.hash == hash) {
+        Some(ZephyrGlyphNoColors {
+            phash: ScVal::U64(phash),
+            ..
+        }) => *phash,
+        _ => return conclude_error(&env, "Glyph not found"),
+    };
+
+    let mut similar: Vec<SimilarGlyph> = glyphs
+        .into_iter()
+        .filter(|glyph| glyph.hash != hash)
+        .filter_map(|glyph| match glyph.phash {
+            ScVal::U64(other) => Some(((phash ^ other).count_ones(), glyph)),
+            _ => None,
+        })
+        .filter(|(distance, _)| *distance <= max_distance)
+        .map(|(distance, glyph)| SimilarGlyph {
+            glyph,
+            distance,
+            duplicate: distance <= DUPLICATE_DISTANCE,
+        })
+        .collect();
+
+    similar.sort_by_key(|similar| similar.distance);
+    similar.truncate(request.limit.unwrap_or(20) as usize);
+
+    env.conclude(&similar);
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetGlyphMetadataRequest {
+    hash: String,
+}
+
+#[derive(Serialize)]
+pub struct PaletteColor {
+    color: String,
+    pixels: u32,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum AttributeValue {
+    Number(u32),
+    Text(String),
+}
+
+#[derive(Serialize)]
+pub struct GlyphAttribute {
+    trait_type: String,
+    value: AttributeValue,
+}
+
+#[derive(Serialize)]
+pub struct GlyphMetadata {
+    name: String,
+    description: String,
+    image: String, // svg data uri
+    hash: String,
+    width: u32,
+    height: u32,
+    minter: Option<String>,
+    owner: Option<String>,
+    palette: Vec<PaletteColor>,
+    attributes: Vec<GlyphAttribute>,
+}
+
+// The usual NFT metadata document, so wallets and marketplaces can show glyphs as they are
+#[no_mangle]
+pub extern "C" fn get_glyph_metadata() {
+    let env = EnvClient::empty();
+    let request: GetGlyphMetadataRequest = env.read_request_body();
+    let glyph = match read_glyph(&env, &request.hash) {
+        Some(glyph) => glyph,
+        None => return conclude_error(&env, "Glyph not found"),
+    };
+    let hash = request.hash.to_ascii_lowercase();
+
+    let glyph_pixels = pixels::decode_pixels(glyph.width, glyph.length, &glyph.colors);
+    let palette = pixels::get_palette(&glyph_pixels);
+    let width = glyph.width.max(1);
+    let height = glyph_pixels.len() as u32 / width;
+    let miners = pixels::decode_colors(&glyph.colors).into_iter().fold(
+        Vec::new(),
+        |mut miners, (miner, ..)| {
+            if !miners.contains(&miner) {
+                miners.push(miner);
+            }
+
+            miners
+        },
+    );
+
+    let svg = render::read_or_render_svg(&env, &glyph);
+
+    env.conclude(&GlyphMetadata {
+        name: format!("Glyph {}", &hash[..hash.len().min(8)]),
+        description: format!("A {} by {} Colorglyph", width, height),
+        image: format!(
+            "data:image/svg+xml;base64,{}",
+            render::encode_base64(svg.as_bytes())
+        ),
+        width,
+        height,
+        minter: scval_to_address_string(&env, &glyph.minter),
+        owner: scval_to_address_string(&env, &glyph.owner),
+        palette: palette
+            .iter()
+            .map(|(color, count)| PaletteColor {
+                color: pixels::color_to_hex(*color),
+                pixels: *count,
+            })
+            .collect(),
+        attributes: vec![
+            GlyphAttribute {
+                trait_type: "Width".to_string(),
+                value: AttributeValue::Number(width),
+            },
+            GlyphAttribute {
+                trait_type: "Height".to_string(),
+                value: AttributeValue::Number(height),
+            },
+            GlyphAttribute {
+                trait_type: "Colors".to_string(),
+                value: AttributeValue::Number(palette.len() as u32),
+            },
+            GlyphAttribute {
+                trait_type: "Miners".to_string(),
+                value: AttributeValue::Number(miners.len() as u32),
+            },
+            GlyphAttribute {
+                trait_type: "Dominant Color".to_string(),
+                value: AttributeValue::Text(
+                    palette
+                        .first()
+                        .map_or(String::new(), |(color, _)| pixels::color_to_hex(*color)),
+                ),
+            },
+        ],
+        hash,
+    });
+}
+
+fn read_glyph(env: &EnvClient, hash: &String) -> Option<ZephyrGlyph> {
+    env.read_filter()
+        .column_equal_to_xdr("hash", &hash_string_to_scval(hash))
+        .read::<ZephyrGlyph>()
+        .unwrap()
+        .into_iter()
+        .next()
+}
+
+#[derive(Serialize)]
+pub struct OfferResponse<T> {
+    #[serde(flatten)]
+    offer: T,
+    price: Option<String>, // amount in the asset's decimals
+    asset_code: Option<String>,
+}
+
+// Swaps and offers in assets without known metadata are left unpriced
+fn to_offer_responses<T: OfferAsset>(env: &EnvClient, offers: Vec<T>) -> Vec<OfferResponse<T>> {
+    let known_assets = env.read::<ZephyrAsset>();
+
+    offers
+        .into_iter()
+        .map(|offer| {
+            let asset = offer
+                .asset()
+                .and_then(|asset| known_assets.iter().find(|known| known.address == *asset));
+            let price = asset.zip(scval_to_i128(offer.amount()));
+
+            OfferResponse {
+                price: price.map(|(asset, amount)| assets::format_amount(amount, asset.decimals)),
+                asset_code: asset.map(assets::asset_code),
+                offer,
+            }
+        })
+        .collect()
 }
 
 #[derive(Serialize, Deserialize)]
@@ -755,11 +1869,798 @@ pub extern "C" fn get_offers() {
     let offers = env
         .read_filter()
         .column_equal_to_xdr("seller", &seller)
-        .column_equal_to_xdr("active", &ScVal::Bool(true))
-        .read::<ZephyrOfferNoActive>()
+        .column_equal_to_xdr("status", &OfferStatus::Active.to_scval())
+        .read::<ZephyrOfferNoStatus>()
         .unwrap();
 
-    env.conclude(&offers);
+    env.conclude(&to_offer_responses(&env, offers));
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OfferSide {
+    Ask,
+    Bid,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetOffersByAssetRequest {
+    asset: String,
+    side: Option<OfferSide>,
+    status: Option<OfferStatus>,
+    min_amount: Option<String>, // i128 as a string
+    max_amount: Option<String>,
+}
+
+#[no_mangle]
+pub extern "C" fn get_offers_by_asset() {
+    let env = EnvClient::empty();
+    let request: GetOffersByAssetRequest = env.read_request_body();
+    let asset = address_string_to_scval(&env, &request.asset);
+    let status = request.status.unwrap_or(OfferStatus::Active).to_scval();
+    let min_amount = match request.min_amount.map(|amount| i128::from_str(&amount)) {
+        Some(Ok(amount)) => Some(amount),
+        Some(Err(_)) => return conclude_error(&env, "Invalid min_amount"),
+        None => None,
+    };
+    let max_amount = match request.max_amount.map(|amount| i128::from_str(&amount)) {
+        Some(Ok(amount)) => Some(amount),
+        Some(Err(_)) => return conclude_error(&env, "Invalid max_amount"),
+        None => None,
+    };
+
+    let mut offers: Vec<ZephyrOffer> = Vec::new();
+
+    // Asks are glyphs being sold for the asset
+    if request.side != Some(OfferSide::Bid) {
+        let asks = env
+            .read_filter()
+            .column_equal_to_xdr("buying", &asset)
+            .column_equal_to_xdr("status", &status)
+            .read::<ZephyrOffer>()
+            .unwrap();
+
+        offers.extend(asks);
+    }
+
+    // Bids are the asset being offered for a glyph
+    if request.side != Some(OfferSide::Ask) {
+        let bids = env
+            .read_filter()
+            .column_equal_to_xdr("selling", &asset)
+            .column_equal_to_xdr("status", &status)
+            .read::<ZephyrOffer>()
+            .unwrap();
+
+        offers.extend(bids);
+    }
+
+    offers.retain(|offer| match scval_to_i128(&offer.amount) {
+        Some(amount) => {
+            min_amount.map_or(true, |min| amount >= min)
+                && max_amount.map_or(true, |max| amount <= max)
+        }
+        None => false,
+    });
+    offers.sort_by_key(|offer| scval_to_i128(&offer.amount));
+
+    env.conclude(&to_offer_responses(&env, offers));
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetGlyphMarketRequest {
+    hash: String,
+}
+
+#[derive(Serialize)]
+pub struct GlyphMarketAsset {
+    asset: ScVal,
+    offers: Vec<OfferResponse<ZephyrOfferNoStatus>>,
+}
+
+#[derive(Serialize)]
+pub struct GetGlyphMarketResponse {
+    asks: Vec<GlyphMarketAsset>,
+    swaps: Vec<OfferResponse<ZephyrOfferNoStatus>>,
+    bids: Vec<GlyphMarketAsset>,
+}
+
+#[no_mangle]
+pub extern "C" fn get_glyph_market() {
+    let env = EnvClient::empty();
+    let request: GetGlyphMarketRequest = env.read_request_body();
+    let hash = hash_string_to_scval(&request.hash);
+
+    // The owner selling this glyph for an asset (or another glyph)
+    let selling = env
+        .read_filter()
+        .column_equal_to_xdr("selling", &hash)
+        .column_equal_to_xdr("status", &OfferStatus::Active.to_scval())
+        .read::<ZephyrOfferNoStatus>()
+        .unwrap();
+    let selling = to_offer_responses(&env, selling);
+
+    // Folks offering an asset in exchange for this glyph
+    let buying = env
+        .read_filter()
+        .column_equal_to_xdr("buying", &hash)
+        .column_equal_to_xdr("status", &OfferStatus::Active.to_scval())
+        .read::<ZephyrOfferNoStatus>()
+        .unwrap();
+    let buying = to_offer_responses(&env, buying);
+
+    let mut asks: Vec<GlyphMarketAsset> = Vec::new();
+    let mut swaps = Vec::new();
+    let mut bids: Vec<GlyphMarketAsset> = Vec::new();
+
+    for offer in selling {
+        if offer.offer.amount == ScVal::Void {
+            swaps.push(offer);
+        } else {
+            group_market_offer(&mut asks, offer.offer.buying.clone(), offer);
+        }
+    }
+
+    // Glyph swaps targeting this glyph are asks on some other glyph, not bids
+    for offer in buying {
+        if offer.offer.amount != ScVal::Void {
+            group_market_offer(&mut bids, offer.offer.selling.clone(), offer);
+        }
+    }
+
+    // Cheapest ask and highest bid first
+    for group in asks.iter_mut() {
+        group
+            .offers
+            .sort_by_key(|offer| scval_to_i128(&offer.offer.amount));
+    }
+
+    for group in bids.iter_mut() {
+        group
+            .offers
+            .sort_by_key(|offer| core::cmp::Reverse(scval_to_i128(&offer.offer.amount)));
+    }
+
+    env.conclude(&GetGlyphMarketResponse { asks, swaps, bids });
+}
+
+fn group_market_offer(
+    groups: &mut Vec<GlyphMarketAsset>,
+    asset: ScVal,
+    offer: OfferResponse<ZephyrOfferNoStatus>,
+) {
+    match groups.iter_mut().find(|group| group.asset == asset) {
+        Some(group) => group.offers.push(offer),
+        None => groups.push(GlyphMarketAsset {
+            asset,
+            offers: vec![offer],
+        }),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FindSwapMatchesRequest {
+    hash: Option<String>,
+    address: Option<String>,
+    max_length: Option<u32>, // glyphs per trade, 2 for straight swaps
+}
+
+#[derive(Serialize)]
+pub struct SwapMatch {
+    glyphs: Vec<ScVal>,
+    offers: Vec<ZephyrOfferNoStatus>, // each one selling `glyphs[i]` for `glyphs[i + 1]`
+}
+
+#[no_mangle]
+pub extern "C" fn find_swap_matches() {
+    let env = EnvClient::empty();
+    let request: FindSwapMatchesRequest = env.read_request_body();
+    let hash = request.hash.map(|hash| hash_string_to_scval(&hash));
+    let address = request
+        .address
+        .map(|address| address_string_to_scval(&env, &address));
+    // Longer cycles get expensive quickly, and are unlikely to all come through anyway
+    let max_length = request.max_length.unwrap_or(2).clamp(2, 6) as usize;
+
+    // Only swaps whose seller still owns the glyph can be part of a trade
+    let swaps: Vec<ZephyrOfferNoStatus> = env
+        .read_filter()
+        .column_equal_to_xdr("status", &OfferStatus::Active.to_scval())
+        .column_equal_to_xdr("validity", &OfferValidity::Valid.to_scval())
+        .read::<ZephyrOfferNoStatus>()
+        .unwrap()
+        .into_iter()
+        .filter(|offer| offer.amount == ScVal::Void)
+        .collect();
+
+    let matches: Vec<SwapMatch> = swaps::find_swap_cycles(&swaps, max_length)
+        .into_iter()
+        .map(|cycle| {
+            let offers: Vec<ZephyrOfferNoStatus> =
+                cycle.iter().map(|&index| swaps[index].clone()).collect();
+
+            SwapMatch {
+                glyphs: offers.iter().map(|offer| offer.selling.clone()).collect(),
+                offers,
+            }
+        })
+        .filter(|swap_match| {
+            hash.as_ref()
+                .map_or(true, |hash| swap_match.glyphs.contains(hash))
+                && address.as_ref().map_or(true, |address| {
+                    swap_match
+                        .offers
+                        .iter()
+                        .any(|offer| offer.seller == *address)
+                })
+        })
+        .collect();
+
+    env.conclude(&matches);
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetMatchesRequest {
+    address: String,
+    status: Option<MatchStatus>,
+}
+
+// Crossed asks and bids the address is on either side of
+#[no_mangle]
+pub extern "C" fn get_matches() {
+    let env = EnvClient::empty();
+    let request: GetMatchesRequest = env.read_request_body();
+    let address = address_string_to_scval(&env, &request.address);
+    let status = request.status.unwrap_or(MatchStatus::Open).to_scval();
+
+    let mut matches = env
+        .read_filter()
+        .column_equal_to_xdr("seller", &address)
+        .column_equal_to_xdr("status", &status)
+        .read::<ZephyrMatch>()
+        .unwrap();
+
+    matches.extend(
+        env.read_filter()
+            .column_equal_to_xdr("buyer", &address)
+            .column_equal_to_xdr("status", &status)
+            .read::<ZephyrMatch>()
+            .unwrap(),
+    );
+
+    env.conclude(&matches);
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetIncomingOffersRequest {
+    address: String,
+}
+
+#[derive(Serialize)]
+pub struct IncomingOffers {
+    bids: Vec<OfferResponse<ZephyrOfferNoStatus>>,
+    swaps: Vec<OfferResponse<ZephyrOfferNoStatus>>,
+}
+
+#[no_mangle]
+pub extern "C" fn get_incoming_offers() {
+    let env = EnvClient::empty();
+    let request: GetIncomingOffersRequest = env.read_request_body();
+    let owner = address_string_to_scval(&env, &request.address);
+
+    let glyphs = env
+        .read_filter()
+        .column_equal_to_xdr("owner", &owner)
+        .read::<ZephyrGlyphNoColors>()
+        .unwrap();
+
+    env.conclude(&read_incoming_offers(&env, &glyphs));
+}
+
+// Offers from other folks looking to get their hands on one of these glyphs
+fn read_incoming_offers(env: &EnvClient, glyphs: &Vec<ZephyrGlyphNoColors>) -> IncomingOffers {
+    let mut bids = Vec::new();
+    let mut swaps = Vec::new();
+
+    for glyph in glyphs.iter() {
+        let offers = env
+            .read_filter()
+            .column_equal_to_xdr("buying", &glyph.hash)
+            .column_equal_to_xdr("status", &OfferStatus::Active.to_scval())
+            .read::<ZephyrOfferNoStatus>()
+            .unwrap();
+
+        for offer in offers {
+            // AssetOffer bids carry an amount, GlyphOffer swaps don't
+            if offer.amount == ScVal::Void {
+                swaps.push(offer);
+            } else {
+                bids.push(offer);
+            }
+        }
+    }
+
+    IncomingOffers {
+        bids: to_offer_responses(&env, bids),
+        swaps: to_offer_responses(&env, swaps),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UnifiedCgQueryRequest {
+    user: String,
+}
+
+#[derive(Serialize)]
+pub struct UnifiedCgQueryResponse {
+    colors: Vec<ZephyrColor>,
+    glyphs: Vec<ZephyrGlyphNoColors>,
+    minted: Vec<ZephyrGlyphNoColors>,
+    offers: Vec<OfferResponse<ZephyrOfferNoStatus>>,
+    incoming_offers: IncomingOffers,
+}
+
+#[no_mangle]
+pub extern "C" fn unified_cg_query() {
+    let env = EnvClient::empty();
+    let request: UnifiedCgQueryRequest = env.read_request_body();
+    let user = address_string_to_scval(&env, &request.user);
+
+    let colors = env
+        .read_filter()
+        .column_equal_to_xdr("owner", &user)
+        .read::<ZephyrColor>()
+        .unwrap();
+
+    let glyphs = env
+        .read_filter()
+        .column_equal_to_xdr("owner", &user)
+        .read::<ZephyrGlyphNoColors>()
+        .unwrap();
+
+    let minted = env
+        .read_filter()
+        .column_equal_to_xdr("minter", &user)
+        .read::<ZephyrGlyphNoColors>()
+        .unwrap();
+
+    let offers = env
+        .read_filter()
+        .column_equal_to_xdr("seller", &user)
+        .column_equal_to_xdr("status", &OfferStatus::Active.to_scval())
+        .read::<ZephyrOfferNoStatus>()
+        .unwrap();
+    let offers = to_offer_responses(&env, offers);
+
+    let incoming_offers = read_incoming_offers(&env, &glyphs);
+
+    env.conclude(&UnifiedCgQueryResponse {
+        colors,
+        glyphs,
+        minted,
+        offers,
+        incoming_offers,
+    });
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OfferHistoryRequest {
+    glyph: Option<String>,
+    seller: Option<String>,
+}
+
+#[no_mangle]
+pub extern "C" fn offer_history() {
+    let env = EnvClient::empty();
+    let request: OfferHistoryRequest = env.read_request_body();
+    let glyph = request.glyph.map(|hash| hash_string_to_scval(&hash));
+    let seller = request
+        .seller
+        .map(|seller| address_string_to_scval(&env, &seller));
+
+    let mut events: Vec<ZephyrOfferEvent> = if let Some(glyph) = &glyph {
+        // Both the glyph's asks and the bids (or swaps) targeting it
+        let mut events = env
+            .read_filter()
+            .column_equal_to_xdr("selling", glyph)
+            .read::<ZephyrOfferEvent>()
+            .unwrap();
+
+        events.extend(
+            env.read_filter()
+                .column_equal_to_xdr("buying", glyph)
+                .read::<ZephyrOfferEvent>()
+                .unwrap(),
+        );
+
+        events
+    } else if let Some(seller) = &seller {
+        env.read_filter()
+            .column_equal_to_xdr("seller", seller)
+            .read::<ZephyrOfferEvent>()
+            .unwrap()
+    } else {
+        env.read::<ZephyrOfferEvent>()
+    };
+
+    if let Some(seller) = &seller {
+        events.retain(|event| event.seller == *seller);
+    }
+
+    events.sort_by_key(|event| event.ledger);
+
+    env.conclude(&events);
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetGlyphPriceHistoryRequest {
+    hash: String,
+    asset: Option<String>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PricePointKind {
+    Ask,
+    Bid,
+    Sale,
+}
+
+#[derive(Serialize)]
+pub struct PricePoint {
+    kind: PricePointKind,
+    status: ScVal, // active when posted, otherwise why it closed
+    asset: ScVal,
+    amount: ScVal,
+    price: Option<String>,
+    asset_code: Option<String>,
+    seller: ScVal, // whoever posted the offer, or sold the glyph
+    buyer: Option<ScVal>,
+    ledger: u32,
+    timestamp: Option<u64>,
+}
+
+/* Asks and bids in an asset as they were posted and closed, along with the glyph's sales.
+    Filled offers show up as their sale, and swaps have no price to chart.
+*/
+#[no_mangle]
+pub extern "C" fn get_glyph_price_history() {
+    let env = EnvClient::empty();
+    let request: GetGlyphPriceHistoryRequest = env.read_request_body();
+    let hash = hash_string_to_scval(&request.hash);
+    let asset = request
+        .asset
+        .map(|asset| address_string_to_scval(&env, &asset));
+    let known_assets = env.read::<ZephyrAsset>();
+
+    let asks = env
+        .read_filter()
+        .column_equal_to_xdr("selling", &hash)
+        .read::<ZephyrOfferEvent>()
+        .unwrap();
+
+    let bids = env
+        .read_filter()
+        .column_equal_to_xdr("buying", &hash)
+        .read::<ZephyrOfferEvent>()
+        .unwrap();
+
+    let sales = env
+        .read_filter()
+        .column_equal_to_xdr("glyph", &hash)
+        .read::<ZephyrSale>()
+        .unwrap();
+
+    let offer_points = asks
+        .into_iter()
+        .map(|event| (PricePointKind::Ask, event.buying.clone(), event))
+        .chain(
+            bids.into_iter()
+                .map(|event| (PricePointKind::Bid, event.selling.clone(), event)),
+        )
+        .filter(|(.., event)| event.status != OfferStatus::Filled.to_scval())
+        .map(|(kind, asset, event)| PricePoint {
+            kind,
+            status: event.status,
+            asset,
+            amount: event.amount,
+            price: None,
+            asset_code: None,
+            seller: event.seller,
+            buyer: None,
+            ledger: event.ledger,
+            timestamp: scval_to_u64(&event.timestamp),
+        });
+
+    let sale_points = sales.into_iter().map(|sale| PricePoint {
+        kind: PricePointKind::Sale,
+        status: OfferStatus::Filled.to_scval(),
+        asset: sale.asset,
+        amount: sale.amount,
+        price: None,
+        asset_code: None,
+        seller: sale.seller,
+        buyer: Some(sale.buyer),
+        ledger: sale.ledger,
+        timestamp: scval_to_u64(&sale.timestamp),
+    });
+
+    let mut points: Vec<PricePoint> = offer_points
+        .chain(sale_points)
+        .filter(|point| matches!(point.asset, ScVal::Address(_)))
+        .filter(|point| asset.as_ref().map_or(true, |asset| point.asset == *asset))
+        .collect();
+
+    for point in points.iter_mut() {
+        if let Some(metadata) = known_assets
+            .iter()
+            .find(|known| known.address == point.asset)
+        {
+            point.price = scval_to_i128(&point.amount)
+                .map(|amount| assets::format_amount(amount, metadata.decimals));
+            point.asset_code = Some(assets::asset_code(metadata));
+        }
+    }
+
+    points.sort_by_key(|point| point.ledger);
+
+    env.conclude(&points);
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetSalesRequest {
+    glyph: Option<String>,
+    address: Option<String>,
+    asset: Option<String>,
+}
+
+#[no_mangle]
+pub extern "C" fn get_sales() {
+    let env = EnvClient::empty();
+    let request: GetSalesRequest = env.read_request_body();
+    let glyph = request.glyph.map(|hash| hash_string_to_scval(&hash));
+    let address = request
+        .address
+        .map(|address| address_string_to_scval(&env, &address));
+    let asset = request
+        .asset
+        .map(|asset| address_string_to_scval(&env, &asset));
+
+    // Narrow down on the most selective column, the remaining filters are applied below
+    let mut sales: Vec<ZephyrSale> = if let Some(glyph) = &glyph {
+        env.read_filter()
+            .column_equal_to_xdr("glyph", glyph)
+            .read::<ZephyrSale>()
+            .unwrap()
+    } else if let Some(address) = &address {
+        let mut sales = env
+            .read_filter()
+            .column_equal_to_xdr("seller", address)
+            .read::<ZephyrSale>()
+            .unwrap();
+
+        sales.extend(
+            env.read_filter()
+                .column_equal_to_xdr("buyer", address)
+                .read::<ZephyrSale>()
+                .unwrap(),
+        );
+
+        sales
+    } else if let Some(asset) = &asset {
+        env.read_filter()
+            .column_equal_to_xdr("asset", asset)
+            .read::<ZephyrSale>()
+            .unwrap()
+    } else {
+        env.read::<ZephyrSale>()
+    };
+
+    sales.retain(|sale| {
+        address.as_ref().map_or(true, |address| {
+            sale.seller == *address || sale.buyer == *address
+        }) && asset.as_ref().map_or(true, |asset| sale.asset == *asset)
+    });
+
+    // Most recent first
+    sales.sort_by_key(|sale| core::cmp::Reverse(sale.ledger));
+
+    env.conclude(&sales);
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetMarketStatsRequest {
+    asset: Option<String>,
+    now: Option<u64>, // defaults to the close time of the latest indexed ledger
+}
+
+#[derive(Serialize)]
+pub struct SalesVolume {
+    volume: String, // i128 as a string
+    count: u32,
+}
+
+#[derive(Serialize)]
+pub struct MarketStats {
+    asset: ScVal,
+    asset_code: Option<String>,
+    decimals: Option<u32>,
+    floor_ask: Option<String>,
+    highest_bid: Option<String>,
+    active_offers: u32,
+    volume_24h: SalesVolume,
+    volume_7d: SalesVolume,
+    volume_all: SalesVolume,
+}
+
+#[no_mangle]
+pub extern "C" fn get_market_stats() {
+    let env = EnvClient::empty();
+    let request: GetMarketStatsRequest = env.read_request_body();
+    let asset = request
+        .asset
+        .map(|asset| address_string_to_scval(&env, &asset));
+
+    let offers = env
+        .read_filter()
+        .column_equal_to_xdr("status", &OfferStatus::Active.to_scval())
+        .read::<ZephyrOfferNoStatus>()
+        .unwrap();
+    let sales = env.read::<ZephyrSale>();
+    let known_assets = env.read::<ZephyrAsset>();
+
+    let now = request
+        .now
+        .unwrap_or_else(|| get_latest_timestamp(&env, &sales));
+
+    // Every asset something is offered or has sold for, swaps aside
+    let mut market_assets: Vec<ScVal> = Vec::new();
+
+    for candidate in offers
+        .iter()
+        .filter_map(|offer| offer.asset())
+        .chain(sales.iter().map(|sale| &sale.asset))
+        .filter(|candidate| matches!(candidate, ScVal::Address(_)))
+    {
+        if !market_assets.contains(candidate)
+            && asset.as_ref().map_or(true, |asset| asset == candidate)
+        {
+            market_assets.push(candidate.clone());
+        }
+    }
+
+    let mut stats: Vec<MarketStats> = market_assets
+        .into_iter()
+        .map(|asset| {
+            let asks: Vec<&ZephyrOfferNoStatus> = offers
+                .iter()
+                .filter(|offer| offer.buying == asset)
+                .collect();
+            let bids: Vec<&ZephyrOfferNoStatus> = offers
+                .iter()
+                .filter(|offer| offer.selling == asset)
+                .collect();
+            let sales: Vec<&ZephyrSale> = sales.iter().filter(|sale| sale.asset == asset).collect();
+            let metadata = known_assets.iter().find(|known| known.address == asset);
+
+            // Only offers known to be fillable set the price
+            let valid_amounts = |offers: &Vec<&ZephyrOfferNoStatus>| -> Vec<i128> {
+                offers
+                    .iter()
+                    .filter(|offer| offer.validity == OfferValidity::Valid.to_scval())
+                    .filter_map(|offer| scval_to_i128(&offer.amount))
+                    .collect()
+            };
+
+            MarketStats {
+                asset_code: metadata.map(assets::asset_code),
+                decimals: metadata.map(|metadata| metadata.decimals),
+                floor_ask: valid_amounts(&asks)
+                    .into_iter()
+                    .min()
+                    .map(|amount| amount.to_string()),
+                highest_bid: valid_amounts(&bids)
+                    .into_iter()
+                    .max()
+                    .map(|amount| amount.to_string()),
+                active_offers: (asks.len() + bids.len()) as u32,
+                volume_24h: get_sales_volume(&sales, now.saturating_sub(24 * 60 * 60)),
+                volume_7d: get_sales_volume(&sales, now.saturating_sub(7 * 24 * 60 * 60)),
+                volume_all: get_sales_volume(&sales, 0),
+                asset,
+            }
+        })
+        .collect();
+
+    // Busiest markets first
+    stats.sort_by_key(|stats| core::cmp::Reverse(stats.volume_all.count));
+
+    env.conclude(&stats);
+}
+
+// A quiet market shouldn't make its last sale "today", so this is the last ledger the indexer saw
+fn get_latest_timestamp(env: &EnvClient, sales: &Vec<ZephyrSale>) -> u64 {
+    match env.read::<ZephyrLatestLedger>().first() {
+        Some(latest) => scval_to_u64(&latest.timestamp).unwrap_or(0),
+        // Nothing's closed since the table was created, the latest sale is the next best thing
+        None => sales
+            .iter()
+            .filter_map(|sale| scval_to_u64(&sale.timestamp))
+            .max()
+            .unwrap_or(0),
+    }
+}
+
+fn get_sales_volume(sales: &Vec<&ZephyrSale>, since: u64) -> SalesVolume {
+    let sales: Vec<&&ZephyrSale> = sales
+        .iter()
+        .filter(|sale| scval_to_u64(&sale.timestamp).map_or(false, |timestamp| timestamp >= since))
+        .collect();
+
+    SalesVolume {
+        volume: sales
+            .iter()
+            .filter_map(|sale| scval_to_i128(&sale.amount))
+            .fold(0i128, |volume, amount| volume.saturating_add(amount))
+            .to_string(),
+        count: sales.len() as u32,
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SeedAssetRequest {
+    address: String,
+    name: String,
+    symbol: String,
+    decimals: u32,
+}
+
+// For assets whose metadata can't be read from their instance storage
+#[no_mangle]
+pub extern "C" fn seed_asset() {
+    let env = EnvClient::empty();
+    let request: SeedAssetRequest = env.read_request_body();
+
+    if request.decimals > assets::MAX_DECIMALS {
+        return conclude_error(&env, "Invalid decimals");
+    }
+
+    let address = address_string_to_scval(&env, &request.address);
+
+    // Whatever the asset says about itself wins, seeding is only a fallback
+    if assets::read_asset_metadata(&env, &address).is_some() {
+        return conclude_error(&env, "Asset metadata is already on chain");
+    }
+
+    let asset = ZephyrAsset {
+        address,
+        name: ScVal::String(ScString(request.name.try_into().unwrap())),
+        symbol: ScVal::String(ScString(request.symbol.try_into().unwrap())),
+        decimals: request.decimals,
+    };
+
+    if assets::get_asset(&env, &asset.address).is_some() {
+        env.update()
+            .column_equal_to_xdr("address", &asset.address)
+            .execute(&asset)
+            .unwrap();
+    } else {
+        env.put(&asset);
+    }
+
+    env.conclude("OK");
+}
+
+// Revalidate every active offer against the indexed owners, e.g. after a backfill
+#[no_mangle]
+pub extern "C" fn validate_offers() {
+    let env = EnvClient::empty();
+
+    for glyph in env.read::<ZephyrGlyphNoColors>() {
+        validate_glyph_offers(&env, &glyph.hash, &glyph.owner);
+    }
+
+    env.conclude("OK");
 }
 
 #[no_mangle]
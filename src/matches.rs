@@ -0,0 +1,230 @@
+use sha2::{Digest, Sha256};
+use zephyr_sdk::{
+    prelude::*,
+    soroban_sdk::xdr::{ScBytes, ScVal, WriteXdr},
+    EnvClient,
+};
+
+use crate::{
+    scval_to_i128,
+    types::{
+        MatchStatus, OfferStatus, OfferValidity, ZephyrMatch, ZephyrMatchStatus, ZephyrOffer,
+        ZephyrOfferNoStatus,
+    },
+};
+
+// Re-check the book of whichever glyph an asset offer is for, swaps have no price to cross
+pub fn update_offer_matches(env: &EnvClient, offer: &ZephyrOfferNoStatus) {
+    if offer.amount == ScVal::Void {
+        return;
+    }
+
+    let glyph = match (&offer.selling, &offer.buying) {
+        (ScVal::Bytes(_), _) => &offer.selling,
+        (_, ScVal::Bytes(_)) => &offer.buying,
+        _ => return,
+    };
+
+    update_glyph_matches(&env, glyph);
+}
+
+// Re-match a glyph's book and open or close its rows in `matches` to suit
+fn update_glyph_matches(env: &EnvClient, glyph: &ScVal) {
+    let asks = read_fillable_offers(&env, "selling", glyph);
+    let bids = read_fillable_offers(&env, "buying", glyph);
+    let crossed = find_crossed_offers(glyph, &asks, &bids);
+
+    let existing = env
+        .read_filter()
+        .column_equal_to_xdr("glyph", glyph)
+        .read::<ZephyrMatch>()
+        .unwrap();
+
+    for existing in existing.iter() {
+        let open = crossed.iter().any(|crossed| crossed.id == existing.id);
+
+        if existing.status == MatchStatus::Open.to_scval() && !open {
+            set_match_status(&env, &existing.id, MatchStatus::Closed);
+        }
+    }
+
+    for crossed in crossed {
+        match existing.iter().find(|existing| existing.id == crossed.id) {
+            None => env.put(&crossed),
+            Some(existing) if existing.status != crossed.status => {
+                set_match_status(&env, &crossed.id, MatchStatus::Open)
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+fn read_fillable_offers(env: &EnvClient, column: &str, glyph: &ScVal) -> Vec<ZephyrOffer> {
+    env.read_filter()
+        .column_equal_to_xdr(column, glyph)
+        .column_equal_to_xdr("status", &OfferStatus::Active.to_scval())
+        .column_equal_to_xdr("validity", &OfferValidity::Valid.to_scval())
+        .read::<ZephyrOffer>()
+        .unwrap()
+}
+
+/* A glyph's book is crossed when someone bids at least what its owner asks in the same asset.
+    Only active offers known to be fillable count, and nobody matches with themselves.
+    Every crossed ask and bid pair is kept open in `matches` until either side goes away.
+*/
+pub fn find_crossed_offers(
+    glyph: &ScVal,
+    asks: &[ZephyrOffer],
+    bids: &[ZephyrOffer],
+) -> Vec<ZephyrMatch> {
+    let fillable = |offer: &&ZephyrOffer| {
+        offer.status == OfferStatus::Active.to_scval()
+            && offer.validity == OfferValidity::Valid.to_scval()
+            && offer.amount != ScVal::Void
+    };
+    let mut crossed = Vec::new();
+
+    for ask in asks.iter().filter(fillable) {
+        for bid in bids.iter().filter(fillable) {
+            let same_asset = ask.buying == bid.selling && ask.seller != bid.seller;
+            let crosses = match (scval_to_i128(&ask.amount), scval_to_i128(&bid.amount)) {
+                (Some(ask_amount), Some(bid_amount)) => bid_amount >= ask_amount,
+                _ => false,
+            };
+
+            if same_asset && crosses {
+                crossed.push(ZephyrMatch {
+                    id: match_id(&ask.id, &bid.id),
+                    glyph: glyph.clone(),
+                    asset: ask.buying.clone(),
+                    seller: ask.seller.clone(),
+                    buyer: bid.seller.clone(),
+                    ask_amount: ask.amount.clone(),
+                    bid_amount: bid.amount.clone(),
+                    status: MatchStatus::Open.to_scval(),
+                });
+            }
+        }
+    }
+
+    crossed
+}
+
+fn set_match_status(env: &EnvClient, id: &ScVal, status: MatchStatus) {
+    env.update()
+        .column_equal_to_xdr("id", id)
+        .execute(&ZephyrMatchStatus {
+            status: status.to_scval(),
+        })
+        .unwrap();
+}
+
+fn match_id(ask_id: &ScVal, bid_id: &ScVal) -> ScVal {
+    let mut hasher = Sha256::new();
+
+    for id in [ask_id, bid_id] {
+        hasher.update(WriteXdr::to_xdr(id, Limits::none()).unwrap());
+    }
+
+    ScVal::Bytes(ScBytes(hasher.finalize().to_vec().try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_crossed_offers;
+    use crate::{
+        i128_to_scval,
+        types::{OfferStatus, OfferValidity, ZephyrOffer},
+    };
+    use zephyr_sdk::soroban_sdk::xdr::ScVal;
+
+    const GLYPH: ScVal = ScVal::U32(10);
+    const ASSET: ScVal = ScVal::U32(30);
+
+    fn offer(id: u32, seller: u32, selling: ScVal, buying: ScVal, amount: i128) -> ZephyrOffer {
+        ZephyrOffer {
+            id: ScVal::U32(id),
+            seller: ScVal::U32(seller),
+            selling,
+            buying,
+            amount: i128_to_scval(amount),
+            status: OfferStatus::Active.to_scval(),
+            validity: OfferValidity::Valid.to_scval(),
+            posted_ledger: 1,
+            closed_ledger: 0,
+        }
+    }
+
+    fn ask(seller: u32, amount: i128) -> ZephyrOffer {
+        offer(1, seller, GLYPH, ASSET, amount)
+    }
+
+    fn bid(seller: u32, amount: i128) -> ZephyrOffer {
+        offer(2, seller, ASSET, GLYPH, amount)
+    }
+
+    #[test]
+    fn crosses_bids_at_or_above_the_ask() {
+        assert_eq!(
+            find_crossed_offers(&GLYPH, &[ask(1, 100)], &[bid(2, 100)]).len(),
+            1
+        );
+        assert_eq!(
+            find_crossed_offers(&GLYPH, &[ask(1, 100)], &[bid(2, 150)]).len(),
+            1
+        );
+        assert!(find_crossed_offers(&GLYPH, &[ask(1, 100)], &[bid(2, 99)]).is_empty());
+    }
+
+    #[test]
+    fn records_both_sides_of_the_match() {
+        let crossed = find_crossed_offers(&GLYPH, &[ask(1, 100)], &[bid(2, 150)]);
+
+        assert_eq!(crossed[0].seller, ScVal::U32(1));
+        assert_eq!(crossed[0].buyer, ScVal::U32(2));
+        assert_eq!(crossed[0].asset, ASSET);
+        assert_eq!(crossed[0].ask_amount, i128_to_scval(100));
+        assert_eq!(crossed[0].bid_amount, i128_to_scval(150));
+    }
+
+    #[test]
+    fn ignores_bids_in_another_asset() {
+        let other = offer(2, 2, ScVal::U32(31), GLYPH, 150);
+
+        assert!(find_crossed_offers(&GLYPH, &[ask(1, 100)], &[other]).is_empty());
+    }
+
+    #[test]
+    fn ignores_owners_bidding_on_their_own_glyph() {
+        assert!(find_crossed_offers(&GLYPH, &[ask(1, 100)], &[bid(1, 150)]).is_empty());
+    }
+
+    #[test]
+    fn ignores_offers_which_cant_be_filled() {
+        let mut closed = bid(2, 150);
+        let mut unfunded = bid(3, 150);
+        let mut stale = ask(1, 100);
+
+        closed.status = OfferStatus::Cancelled.to_scval();
+        unfunded.validity = OfferValidity::Unfunded.to_scval();
+        stale.validity = OfferValidity::Stale.to_scval();
+
+        assert!(find_crossed_offers(&GLYPH, &[ask(1, 100)], &[closed, unfunded]).is_empty());
+        assert!(find_crossed_offers(&GLYPH, &[stale], &[bid(2, 150)]).is_empty());
+    }
+
+    #[test]
+    fn gives_each_pair_its_own_id() {
+        let crossed = find_crossed_offers(
+            &GLYPH,
+            &[ask(1, 100)],
+            &[
+                offer(2, 2, ASSET, GLYPH, 150),
+                offer(3, 3, ASSET, GLYPH, 150),
+            ],
+        );
+
+        assert_eq!(crossed.len(), 2);
+        assert_ne!(crossed[0].id, crossed[1].id);
+    }
+}
@@ -0,0 +1,429 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zephyr_sdk::soroban_sdk::xdr::{ScMap, ScMapEntry, ScVal, ScVec};
+
+// Every pixel one miner's color was painted on
+#[derive(Serialize, Clone, Debug)]
+pub struct ColorIndexes {
+    pub miner: ScVal,
+    pub color: String, // hex, e.g. "#ff0000"
+    pub indexes: Vec<u32>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DecodedColors {
+    pub colors: Vec<ColorIndexes>,
+    pub pixels: Vec<Option<String>>, // row by row, None where nothing was painted
+}
+
+/* The contract stores colors as `Map<miner, Map<color, Vec<index>>>`,
+    with colors as 0xRRGGBB and indexes running left to right, top to bottom.
+*/
+pub fn decode_colors(colors: &ScVal) -> Vec<(ScVal, u32, Vec<u32>)> {
+    let mut decoded = Vec::new();
+
+    if let ScVal::Map(Some(ScMap(miners))) = colors {
+        for ScMapEntry { key: miner, val } in miners.iter() {
+            if let ScVal::Map(Some(ScMap(colors))) = val {
+                for ScMapEntry { key, val } in colors.iter() {
+                    if let (ScVal::U32(color), ScVal::Vec(Some(ScVec(indexes)))) = (key, val) {
+                        let indexes = indexes
+                            .iter()
+                            .filter_map(|index| match index {
+                                ScVal::U32(index) => Some(*index),
+                                _ => None,
+                            })
+                            .collect();
+
+                        decoded.push((miner.clone(), *color, indexes));
+                    }
+                }
+            }
+        }
+    }
+
+    decoded
+}
+
+/* Mirrors how `glyph_mint` derives the storage hash: every painted pixel's color in index order,
+    each as little endian u32 bytes, followed by the width. Unpainted pixels are skipped.
+*/
+pub fn glyph_hash(width: u32, pixels: &[Option<u32>]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+
+    for color in pixels.iter().flatten() {
+        hasher.update(color.to_le_bytes());
+    }
+
+    hasher.update(width.to_le_bytes());
+    hasher.finalize().into()
+}
+
+/* `length` is the glyph's pixel count, laid out in rows of `width`.
+    The last row is padded out so clients always get a full rectangle.
+    A pixel painted by more than one miner keeps whichever color comes last in the map's key order,
+    miner address then color, which says nothing about who painted it last.
+*/
+pub fn decode_pixels(width: u32, length: u32, colors: &ScVal) -> Vec<Option<u32>> {
+    let decoded = decode_colors(colors);
+    let painted = decoded
+        .iter()
+        .flat_map(|(_, _, indexes)| indexes.iter())
+        .max()
+        .map_or(0, |index| index + 1);
+    let size = length.max(painted);
+    let size = match width {
+        0 => size,
+        width => size.div_ceil(width) * width,
+    };
+
+    let mut pixels = vec![None; size as usize];
+
+    for (_, color, indexes) in decoded.iter() {
+        for index in indexes.iter() {
+            pixels[*index as usize] = Some(*color);
+        }
+    }
+
+    pixels
+}
+
+pub fn decode_glyph(width: u32, length: u32, colors: &ScVal) -> DecodedColors {
+    DecodedColors {
+        colors: decode_colors(colors)
+            .into_iter()
+            .map(|(miner, color, indexes)| ColorIndexes {
+                miner,
+                color: color_to_hex(color),
+                indexes,
+            })
+            .collect(),
+        pixels: decode_pixels(width, length, colors)
+            .into_iter()
+            .map(|pixel| pixel.map(color_to_hex))
+            .collect(),
+    }
+}
+
+// Distinct colors with how many pixels they cover, most used first
+pub fn get_palette(pixels: &[Option<u32>]) -> Vec<(u32, u32)> {
+    let mut palette: Vec<(u32, u32)> = Vec::new();
+
+    for color in pixels.iter().flatten() {
+        match palette.iter_mut().find(|(existing, _)| existing == color) {
+            Some((_, count)) => *count += 1,
+            None => palette.push((*color, 1)),
+        }
+    }
+
+    palette.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    palette
+}
+
+// Share of the pixels left unpainted, in basis points
+pub fn get_transparent_bps(pixels: &[Option<u32>]) -> u32 {
+    let unset = pixels.iter().filter(|pixel| pixel.is_none()).count();
+
+    match pixels.len() {
+        0 => 0,
+        total => (unset * 10_000 / total) as u32,
+    }
+}
+
+pub fn color_to_hex(color: u32) -> String {
+    format!("#{:06x}", color & 0xffffff)
+}
+
+// The other way around, None unless it's exactly "#rrggbb"
+pub fn hex_to_color(hex: &str) -> Option<u32> {
+    let hex = hex.strip_prefix('#')?;
+
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    u32::from_str_radix(hex, 16).ok()
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorSpace {
+    Rgb, // euclidean distance, 0 to ~441
+    Lab, // CIE76 delta E, roughly 2.3 is the smallest difference people notice
+}
+
+pub fn color_distance(a: u32, b: u32, space: ColorSpace) -> f64 {
+    let (a, b) = match space {
+        ColorSpace::Rgb => (to_rgb(a), to_rgb(b)),
+        ColorSpace::Lab => (to_lab(a), to_lab(b)),
+    };
+
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+fn to_rgb(color: u32) -> [f64; 3] {
+    [
+        ((color >> 16) & 0xff) as f64,
+        ((color >> 8) & 0xff) as f64,
+        (color & 0xff) as f64,
+    ]
+}
+
+// sRGB to CIE Lab under a D65 white point
+fn to_lab(color: u32) -> [f64; 3] {
+    let [r, g, b] = to_rgb(color).map(|channel| {
+        let channel = channel / 255.0;
+
+        if channel <= 0.04045 {
+            channel / 12.92
+        } else {
+            ((channel + 0.055) / 1.055).powf(2.4)
+        }
+    });
+
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let [x, y, z] = [x, y, z].map(|t| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    });
+
+    [116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z)]
+}
+
+/* A difference hash: the glyph is shrunk to 9x8 grayscale cells and each bit records whether
+    a cell is brighter than its neighbour to the right. Similar images differ in few bits,
+    regardless of their size. Unpainted pixels count as white.
+*/
+pub fn perceptual_hash(width: u32, pixels: &[Option<u32>]) -> u64 {
+    let width = width.max(1) as usize;
+    let height = (pixels.len() / width).max(1);
+
+    let luminance = |pixel: &Option<u32>| match pixel {
+        Some(color) => {
+            let [r, g, b] = to_rgb(*color);
+
+            0.299 * r + 0.587 * g + 0.114 * b
+        }
+        None => 255.0,
+    };
+
+    // Average of the source pixels each cell covers, or the nearest one for tiny glyphs
+    let cell = |cx: usize, cy: usize, columns: usize, rows: usize| {
+        let x0 = cx * width / columns;
+        let x1 = ((cx + 1) * width / columns).max(x0 + 1);
+        let y0 = cy * height / rows;
+        let y1 = ((cy + 1) * height / rows).max(y0 + 1);
+        let mut sum = 0.0;
+        let mut count = 0.0;
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                sum += pixels.get(y * width + x).map_or(255.0, luminance);
+                count += 1.0;
+            }
+        }
+
+        sum / count
+    };
+
+    let mut hash = 0u64;
+
+    for cy in 0..8 {
+        for cx in 0..8 {
+            hash <<= 1;
+
+            if cell(cx, cy, 9, 8) > cell(cx + 1, cy, 9, 8) {
+                hash |= 1;
+            }
+        }
+    }
+
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        color_distance, color_to_hex, decode_pixels, get_palette, get_transparent_bps, glyph_hash,
+        hex_to_color, perceptual_hash, ColorSpace,
+    };
+    use zephyr_sdk::soroban_sdk::xdr::{ScMap, ScMapEntry, ScVal, ScVec};
+
+    // (color, indexes) painted by one miner
+    type Painted<'a> = &'a [(u32, &'a [u32])];
+
+    // The contract's `Map<miner, Map<color, Vec<index>>>`, miners in the order given
+    fn colors(miners: &[(u32, Painted)]) -> ScVal {
+        let map = |entries: Vec<ScMapEntry>| ScVal::Map(Some(ScMap(entries.try_into().unwrap())));
+
+        map(miners
+            .iter()
+            .map(|(miner, colors)| ScMapEntry {
+                key: ScVal::U32(*miner),
+                val: map(colors
+                    .iter()
+                    .map(|(color, indexes)| ScMapEntry {
+                        key: ScVal::U32(*color),
+                        val: ScVal::Vec(Some(ScVec(
+                            indexes
+                                .iter()
+                                .map(|index| ScVal::U32(*index))
+                                .collect::<Vec<ScVal>>()
+                                .try_into()
+                                .unwrap(),
+                        ))),
+                    })
+                    .collect()),
+            })
+            .collect())
+    }
+
+    #[test]
+    fn decodes_pixels_row_by_row() {
+        let colors = colors(&[(1, &[(0xff0000, &[0, 3]), (0x00ff00, &[1])])]);
+
+        assert_eq!(
+            decode_pixels(2, 4, &colors),
+            vec![Some(0xff0000), Some(0x00ff00), None, Some(0xff0000)]
+        );
+    }
+
+    #[test]
+    fn pads_out_the_last_row() {
+        let first_pixel = colors(&[(1, &[(0xff0000, &[0])])]);
+        let past_length = colors(&[(1, &[(0xff0000, &[2])])]);
+
+        assert_eq!(
+            decode_pixels(3, 4, &first_pixel),
+            vec![Some(0xff0000), None, None, None, None, None]
+        );
+        // Pixels painted past `length` still make it in
+        assert_eq!(
+            decode_pixels(2, 1, &past_length),
+            vec![None, None, Some(0xff0000), None]
+        );
+    }
+
+    #[test]
+    fn keeps_the_last_miner_in_map_order_on_overlapping_pixels() {
+        let colors = colors(&[(1, &[(0xff0000, &[0, 1])]), (2, &[(0x0000ff, &[1])])]);
+
+        assert_eq!(
+            decode_pixels(2, 2, &colors),
+            vec![Some(0xff0000), Some(0x0000ff)]
+        );
+    }
+
+    #[test]
+    fn orders_the_palette_by_use_then_color() {
+        let pixels = [
+            Some(0x0000ff),
+            Some(0xff0000),
+            None,
+            Some(0x00ff00),
+            Some(0xff0000),
+            Some(0x00ff00),
+            Some(0xffffff),
+        ];
+
+        assert_eq!(
+            get_palette(&pixels),
+            vec![(0x00ff00, 2), (0xff0000, 2), (0x0000ff, 1), (0xffffff, 1)]
+        );
+        assert!(get_palette(&[None, None]).is_empty());
+    }
+
+    #[test]
+    fn measures_transparency_in_basis_points() {
+        assert_eq!(get_transparent_bps(&[]), 0);
+        assert_eq!(get_transparent_bps(&[None, None, None]), 10_000);
+        assert_eq!(get_transparent_bps(&[Some(0xff0000), Some(0x00ff00)]), 0);
+        assert_eq!(
+            get_transparent_bps(&[Some(0xff0000), None, None, None]),
+            7_500
+        );
+    }
+
+    #[test]
+    fn parses_hex_colors() {
+        assert_eq!(hex_to_color("#ff0000"), Some(0xff0000));
+        assert_eq!(hex_to_color("#00FF7f"), Some(0x00ff7f));
+        assert_eq!(hex_to_color(&color_to_hex(0x123456)), Some(0x123456));
+    }
+
+    #[test]
+    fn rejects_malformed_hex_colors() {
+        assert_eq!(hex_to_color("ff0000"), None);
+        assert_eq!(hex_to_color("#f00"), None);
+        assert_eq!(hex_to_color("#ff00000"), None);
+        assert_eq!(hex_to_color("#gg0000"), None);
+        assert_eq!(hex_to_color("#+f0000"), None);
+    }
+
+    /* Worked out by hand from the scheme `glyph_hash` documents rather than taken from a minted glyph,
+        so this pins the scheme down but can't catch it drifting from the contract's.
+    */
+    #[test]
+    fn hashes_painted_pixels_and_width() {
+        let pixels = [Some(0xff0000), None, Some(0x00ff00), Some(0x0000ff)];
+        let painted = [Some(0xff0000), Some(0x00ff00), Some(0x0000ff)];
+        let expected = "8a0da79318e12faba5020fb1a69607dbb0fa2d1a8dcda628dbc9b0617e7a379f";
+
+        assert_eq!(hex::encode(glyph_hash(2, &pixels)), expected);
+        // Unpainted pixels don't count, the width does
+        assert_eq!(hex::encode(glyph_hash(2, &painted)), expected);
+        assert_ne!(hex::encode(glyph_hash(4, &pixels)), expected);
+    }
+
+    // A `width` by `height` glyph left unpainted up to `edge` and painted black from there on
+    fn split(width: u32, height: u32, edge: u32) -> Vec<Option<u32>> {
+        (0..width * height)
+            .map(|index| (index % width >= edge).then_some(0x000000))
+            .collect()
+    }
+
+    #[test]
+    fn hashes_brightness_steps_from_left_to_right() {
+        // Only the step from the fourth to the fifth cell gets darker, in every row
+        assert_eq!(perceptual_hash(9, &split(9, 8, 4)), 0x1010101010101010);
+        // Mirrored it goes from black to white, which never does
+        let mirrored: Vec<_> = split(9, 8, 4).into_iter().rev().collect();
+
+        assert_eq!(perceptual_hash(9, &mirrored), 0);
+        assert_eq!(perceptual_hash(9, &[None; 72]), 0);
+    }
+
+    #[test]
+    fn hashes_the_same_picture_the_same_at_any_size() {
+        let small = perceptual_hash(9, &split(9, 8, 4));
+
+        assert_eq!(perceptual_hash(18, &split(18, 16, 8)), small);
+        assert_eq!(perceptual_hash(36, &split(36, 32, 16)), small);
+        assert_ne!(perceptual_hash(9, &split(9, 8, 6)), small);
+    }
+
+    #[test]
+    fn measures_rgb_distance() {
+        assert_eq!(color_distance(0x336699, 0x336699, ColorSpace::Rgb), 0.0);
+        assert_eq!(color_distance(0x000000, 0xff0000, ColorSpace::Rgb), 255.0);
+        assert!((color_distance(0x000000, 0xffffff, ColorSpace::Rgb) - 441.67).abs() < 0.01);
+    }
+
+    #[test]
+    fn measures_lab_distance() {
+        assert_eq!(color_distance(0x336699, 0x336699, ColorSpace::Lab), 0.0);
+        // Black and white are the whole lightness range apart
+        assert!((color_distance(0x000000, 0xffffff, ColorSpace::Lab) - 100.0).abs() < 0.1);
+        assert_eq!(
+            color_distance(0xff0000, 0x00ff00, ColorSpace::Lab),
+            color_distance(0x00ff00, 0xff0000, ColorSpace::Lab)
+        );
+    }
+}
@@ -0,0 +1,342 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use png::{BitDepth, ColorType, Encoder};
+use zephyr_sdk::{
+    prelude::*,
+    soroban_sdk::xdr::{ScBytes, ScString, ScSymbol, ScVal},
+    EnvClient,
+};
+
+use crate::{
+    pixels::{color_to_hex, decode_pixels},
+    types::{ZephyrGlyph, ZephyrGlyphRender, ZephyrGlyphRenderEmpty, ZephyrGlyphRenderOutput},
+};
+
+// Roughly the output widths galleries ask for, PNGs are cached at the scale closest to each
+pub const PNG_RENDER_SIZES: [u32; 2] = [256, 1024];
+
+// Neither side of a PNG may go past this many pixels, the whole image is held in the module's memory
+pub const MAX_PNG_SIZE: u32 = 2048;
+
+/* One rect per horizontal run of the same color keeps the output small,
+    and crispEdges stops viewers from blurring the pixels together when scaled.
+*/
+pub fn render_svg(
+    width: u32,
+    pixels: &[Option<u32>],
+    scale: u32,
+    background: Option<&str>,
+) -> String {
+    let width = width.max(1);
+    let height = pixels.len() as u32 / width;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" shape-rendering=\"crispEdges\">",
+        width * scale,
+        height * scale,
+        width,
+        height
+    );
+
+    if let Some(background) = background {
+        svg.push_str(&format!(
+            "<rect width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+            width, height, background
+        ));
+    }
+
+    for (y, row) in pixels.chunks(width as usize).enumerate() {
+        let mut x = 0;
+
+        while x < row.len() {
+            let run = row[x..]
+                .iter()
+                .take_while(|pixel| **pixel == row[x])
+                .count();
+
+            if let Some(color) = row[x] {
+                svg.push_str(&format!(
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"1\" fill=\"{}\"/>",
+                    x,
+                    y,
+                    run,
+                    color_to_hex(color)
+                ));
+            }
+
+            x += run;
+        }
+    }
+
+    svg.push_str("</svg>");
+
+    svg
+}
+
+// Backgrounds end up inside an attribute, so only hex colors are let through
+pub fn parse_background(background: &str) -> Option<String> {
+    let hex = background.strip_prefix('#')?;
+    let valid = matches!(hex.len(), 3 | 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit());
+
+    valid.then(|| format!("#{}", hex.to_ascii_lowercase()))
+}
+
+// Each glyph pixel becomes a `scale` by `scale` square, unpainted ones take the background
+pub fn render_png(
+    width: u32,
+    pixels: &[Option<u32>],
+    scale: u32,
+    background: Option<&str>,
+) -> Option<Vec<u8>> {
+    let width = width.max(1);
+    let height = pixels.len() as u32 / width;
+    let background = background.map_or([0, 0, 0, 0], hex_to_rgba);
+
+    if scale == 0 || scale > max_png_scale(width, height) {
+        return None;
+    }
+
+    let line_size = (width as usize)
+        .checked_mul(scale as usize)?
+        .checked_mul(4)?;
+    let mut data = Vec::with_capacity(
+        line_size
+            .checked_mul(height as usize)?
+            .checked_mul(scale as usize)?,
+    );
+
+    for row in pixels.chunks(width as usize) {
+        let mut line = Vec::with_capacity(line_size);
+
+        for pixel in row.iter() {
+            let rgba = match pixel {
+                Some(color) => [(color >> 16) as u8, (color >> 8) as u8, *color as u8, 255],
+                None => background,
+            };
+
+            for _ in 0..scale {
+                line.extend_from_slice(&rgba);
+            }
+        }
+
+        for _ in 0..scale {
+            data.extend_from_slice(&line);
+        }
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = Encoder::new(&mut png, width * scale, height * scale);
+
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(BitDepth::Eight);
+
+    let mut writer = encoder.write_header().unwrap();
+
+    writer.write_image_data(&data).unwrap();
+    writer.finish().unwrap();
+
+    Some(png)
+}
+
+// The biggest scale which keeps both sides within `MAX_PNG_SIZE`, 0 if the glyph itself doesn't fit
+pub fn max_png_scale(width: u32, height: u32) -> u32 {
+    MAX_PNG_SIZE / width.max(height).max(1)
+}
+
+pub fn render_png_base64(
+    width: u32,
+    pixels: &[Option<u32>],
+    scale: u32,
+    background: Option<&str>,
+) -> Option<String> {
+    render_png(width, pixels, scale, background).map(|png| encode_base64(&png))
+}
+
+pub fn encode_base64(png: &[u8]) -> String {
+    STANDARD.encode(png)
+}
+
+// Expects the output of `parse_background`
+fn hex_to_rgba(hex: &str) -> [u8; 4] {
+    let hex = hex.trim_start_matches('#');
+    let hex: String = match hex.len() {
+        3 => hex.chars().flat_map(|c| [c, c]).collect(),
+        _ => hex.to_string(),
+    };
+    let channel = |i: usize| {
+        hex.get(i * 2..i * 2 + 2)
+            .map_or(255, |channel| u8::from_str_radix(channel, 16).unwrap())
+    };
+
+    [channel(0), channel(1), channel(2), channel(3)]
+}
+
+/* Rendering is done once whenever a glyph's colors are written rather than on every request.
+    Only the plain renders are cached, an SVG at its natural size and PNGs at the standard sizes.
+    Renders left over from before are blanked out first, a new width can change the PNG scales.
+*/
+pub fn cache_glyph_renders(env: &EnvClient, hash: &ScVal, width: u32, pixels: &[Option<u32>]) {
+    clear_glyph_renders(&env, hash);
+
+    let svg = render_svg(width, pixels, 1, None);
+
+    put_glyph_render(
+        &env,
+        hash,
+        "svg",
+        1,
+        ScVal::String(ScString(svg.try_into().unwrap())),
+    );
+
+    let height = pixels.len() as u32 / width.max(1);
+
+    for scale in png_render_scales(width, height) {
+        if let Some(png) = render_png(width, pixels, scale, None) {
+            put_glyph_render(
+                &env,
+                hash,
+                "png",
+                scale,
+                ScVal::Bytes(ScBytes(png.try_into().unwrap())),
+            );
+        }
+    }
+}
+
+/* Read endpoints never write, a glyph indexed before its renders were cached is rendered on the fly.
+    `glyph_renders` has no primary key, so two requests racing to cache the same render would
+    leave duplicate rows behind.
+*/
+pub fn read_or_render_svg(env: &EnvClient, glyph: &ZephyrGlyph) -> String {
+    if let Some(ScVal::String(ScString(svg))) = read_glyph_render(&env, &glyph.hash, "svg", 1) {
+        return svg.to_utf8_string_lossy();
+    }
+
+    let pixels = decode_pixels(glyph.width, glyph.length, &glyph.colors);
+
+    render_svg(glyph.width, &pixels, 1, None)
+}
+
+// `scale` is expected to be one of `png_render_scales`
+pub fn read_or_render_png(env: &EnvClient, glyph: &ZephyrGlyph, scale: u32) -> Option<Vec<u8>> {
+    if let Some(ScVal::Bytes(ScBytes(png))) = read_glyph_render(&env, &glyph.hash, "png", scale) {
+        return Some(png.to_vec());
+    }
+
+    let pixels = decode_pixels(glyph.width, glyph.length, &glyph.colors);
+
+    render_png(glyph.width, &pixels, scale, None)
+}
+
+fn clear_glyph_renders(env: &EnvClient, hash: &ScVal) {
+    let existing = env
+        .read_filter()
+        .column_equal_to_xdr("hash", hash)
+        .read::<ZephyrGlyphRenderEmpty>()
+        .unwrap();
+
+    if existing.len() > 0 {
+        env.update()
+            .column_equal_to_xdr("hash", hash)
+            .execute(&ZephyrGlyphRenderOutput {
+                render: ScVal::Void,
+            })
+            .unwrap();
+    }
+}
+
+pub fn png_render_scales(width: u32, height: u32) -> Vec<u32> {
+    let max_scale = max_png_scale(width, height);
+    let mut scales: Vec<u32> = PNG_RENDER_SIZES
+        .iter()
+        .map(|size| (size / width.max(1)).clamp(1, max_scale.max(1)))
+        .filter(|scale| *scale <= max_scale)
+        .collect();
+
+    scales.dedup();
+
+    scales
+}
+
+fn put_glyph_render(env: &EnvClient, hash: &ScVal, format: &str, scale: u32, render: ScVal) {
+    let format = ScVal::Symbol(ScSymbol(format.try_into().unwrap()));
+    let existing = env
+        .read_filter()
+        .column_equal_to_xdr("hash", hash)
+        .column_equal_to_xdr("format", &format)
+        .column_equal_to("scale", scale)
+        .read::<ZephyrGlyphRenderOutput>()
+        .unwrap();
+
+    if existing.len() == 0 {
+        env.put(&ZephyrGlyphRender {
+            hash: hash.clone(),
+            format,
+            scale,
+            render,
+        });
+    } else {
+        env.update()
+            .column_equal_to_xdr("hash", hash)
+            .column_equal_to_xdr("format", &format)
+            .column_equal_to("scale", scale)
+            .execute(&ZephyrGlyphRenderOutput { render })
+            .unwrap();
+    }
+}
+
+fn read_glyph_render(env: &EnvClient, hash: &ScVal, format: &str, scale: u32) -> Option<ScVal> {
+    env.read_filter()
+        .column_equal_to_xdr("hash", hash)
+        .column_equal_to_xdr(
+            "format",
+            &ScVal::Symbol(ScSymbol(format.try_into().unwrap())),
+        )
+        .column_equal_to("scale", scale)
+        .read::<ZephyrGlyphRenderOutput>()
+        .unwrap()
+        .into_iter()
+        .next()
+        .map(|render| render.render)
+        .filter(|render| *render != ScVal::Void)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{max_png_scale, parse_background, png_render_scales, render_png, MAX_PNG_SIZE};
+
+    #[test]
+    fn accepts_hex_backgrounds() {
+        assert_eq!(parse_background("#FF0000"), Some("#ff0000".to_string()));
+        assert_eq!(parse_background("#abc"), Some("#abc".to_string()));
+        assert_eq!(parse_background("#11223344"), Some("#11223344".to_string()));
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        assert_eq!(parse_background("red"), None);
+        assert_eq!(parse_background("ff0000"), None);
+        assert_eq!(parse_background("#12"), None);
+        assert_eq!(parse_background("#gggggg"), None);
+        assert_eq!(parse_background("#fff\" onload=\"alert(1)"), None);
+    }
+
+    #[test]
+    fn bounds_png_scales_by_the_longest_side() {
+        assert_eq!(max_png_scale(16, 8), 128);
+        assert_eq!(max_png_scale(8, 2048), 1);
+        assert_eq!(max_png_scale(MAX_PNG_SIZE + 1, 1), 0);
+        assert_eq!(png_render_scales(16, 16), vec![16, 64]);
+        assert_eq!(png_render_scales(1, 1024), vec![2]);
+        assert_eq!(png_render_scales(4096, 1), vec![]);
+    }
+
+    #[test]
+    fn refuses_pngs_past_the_size_limit() {
+        let pixels = [Some(0xff0000), None, None, Some(0x0000ff)];
+
+        assert!(render_png(2, &pixels, MAX_PNG_SIZE / 2, None).is_some());
+        assert!(render_png(2, &pixels, MAX_PNG_SIZE / 2 + 1, None).is_none());
+        assert!(render_png(2, &pixels, u32::MAX, None).is_none());
+        assert!(render_png(2, &pixels, 0, None).is_none());
+    }
+}
@@ -0,0 +1,300 @@
+use zephyr_sdk::soroban_sdk::xdr::ScVal;
+
+use crate::types::{OfferStatus, ZephyrOfferNoStatus, ZephyrSale};
+
+// An offer entry a transaction dropped some offers from, as the values they're indexed with
+#[derive(Clone, Debug)]
+pub enum RemovedOffer {
+    // `AssetOffer(glyph, asset, amount)` and the bidders who left it
+    Bids {
+        glyph: ScVal,
+        asset: ScVal,
+        amount: ScVal,
+        bidders: Vec<ScVal>,
+    },
+    // `GlyphOffer(glyph)` and the (buying, amount) asks which went, amount is Void for swaps
+    Asks {
+        glyph: ScVal,
+        asks: Vec<(ScVal, ScVal)>,
+    },
+}
+
+/* The (asset, amount) the glyph traded for, if one of the removed offers explains the transfer.
+    `sent_offers` are the (asset, amount) asks passed in the call, `owner_changes` every
+    (glyph, previous owner, new owner) of the transaction.
+*/
+pub fn find_filled_offer(
+    removed_offers: &[RemovedOffer],
+    sent_offers: &[(ScVal, ScVal)],
+    owner_changes: &[(ScVal, ScVal, ScVal)],
+    glyph: &ScVal,
+    seller: &ScVal,
+    buyer: &ScVal,
+) -> Option<(ScVal, ScVal)> {
+    // A bid was filled, the buyer dropped out of the AssetOffer they now own the glyph for
+    for removed in removed_offers.iter() {
+        if let RemovedOffer::Bids {
+            glyph: hash,
+            asset,
+            amount,
+            bidders,
+        } = removed
+        {
+            if hash == glyph && bidders.contains(buyer) {
+                return Some((asset.clone(), amount.clone()));
+            }
+        }
+    }
+
+    // An ask was filled, the seller's GlyphOffer went away along with the glyph
+    for removed in removed_offers.iter() {
+        if let RemovedOffer::Asks { glyph: hash, asks } = removed {
+            if hash != glyph {
+                continue;
+            }
+
+            for (buying, _) in asks.iter().filter(|(_, amount)| *amount == ScVal::Void) {
+                // A swap only counts if the other glyph went the opposite way
+                if owner_changes.iter().any(|(hash, previous, new)| {
+                    hash == buying && previous == buyer && new == seller
+                }) {
+                    return Some((buying.clone(), ScVal::Void));
+                }
+            }
+
+            // A plain transfer also wipes the asks, so the buyer must have sent the filled one in
+            let ask = asks
+                .iter()
+                .filter(|(_, amount)| *amount != ScVal::Void)
+                .find(|ask| sent_offers.contains(ask));
+
+            if let Some(ask) = ask {
+                return Some(ask.clone());
+            }
+        }
+    }
+
+    None
+}
+
+// Why an offer went away, given the transaction's sales and (glyph, previous owner, new owner)s
+pub fn get_close_reason(
+    sales: &[ZephyrSale],
+    owner_changes: &[(ScVal, ScVal, ScVal)],
+    offer: &ZephyrOfferNoStatus,
+) -> OfferStatus {
+    let filled = sales.iter().any(|sale| {
+        let ask = sale.glyph == offer.selling && sale.seller == offer.seller;
+        let bid = sale.glyph == offer.buying && sale.buyer == offer.seller;
+        let asset = if ask { &offer.buying } else { &offer.selling };
+
+        (ask || bid) && sale.asset == *asset && sale.amount == offer.amount
+    });
+
+    if filled {
+        return OfferStatus::Filled;
+    }
+
+    // The glyph moved without this offer being the reason
+    let transferred = owner_changes
+        .iter()
+        .any(|(hash, ..)| *hash == offer.selling || *hash == offer.buying);
+
+    if transferred {
+        OfferStatus::Invalidated
+    } else {
+        OfferStatus::Cancelled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_filled_offer, get_close_reason, RemovedOffer};
+    use crate::types::{OfferStatus, ZephyrOfferNoStatus, ZephyrSale};
+    use zephyr_sdk::soroban_sdk::xdr::ScVal;
+
+    // Glyphs are 1x, people 2x and assets 3x, amounts are plain u32s
+    const GLYPH: ScVal = ScVal::U32(10);
+    const OTHER_GLYPH: ScVal = ScVal::U32(11);
+    const SELLER: ScVal = ScVal::U32(20);
+    const BUYER: ScVal = ScVal::U32(21);
+    const ASSET: ScVal = ScVal::U32(30);
+    const AMOUNT: ScVal = ScVal::U32(100);
+
+    fn bids(bidders: Vec<ScVal>) -> RemovedOffer {
+        RemovedOffer::Bids {
+            glyph: GLYPH,
+            asset: ASSET,
+            amount: AMOUNT,
+            bidders,
+        }
+    }
+
+    fn asks(asks: Vec<(ScVal, ScVal)>) -> RemovedOffer {
+        RemovedOffer::Asks { glyph: GLYPH, asks }
+    }
+
+    fn sold(
+        removed: &[RemovedOffer],
+        sent: &[(ScVal, ScVal)],
+        owner_changes: &[(ScVal, ScVal, ScVal)],
+    ) -> Option<(ScVal, ScVal)> {
+        find_filled_offer(removed, sent, owner_changes, &GLYPH, &SELLER, &BUYER)
+    }
+
+    #[test]
+    fn finds_filled_bids() {
+        let removed = [bids(vec![BUYER])];
+
+        assert_eq!(sold(&removed, &[], &[]), Some((ASSET, AMOUNT)));
+    }
+
+    #[test]
+    fn ignores_bids_from_someone_else() {
+        let removed = [bids(vec![SELLER])];
+
+        assert_eq!(sold(&removed, &[], &[]), None);
+    }
+
+    #[test]
+    fn finds_filled_asks_the_buyer_sent() {
+        let removed = [asks(vec![(ASSET, ScVal::U32(50)), (ASSET, AMOUNT)])];
+
+        assert_eq!(
+            sold(&removed, &[(ASSET, AMOUNT)], &[]),
+            Some((ASSET, AMOUNT))
+        );
+    }
+
+    #[test]
+    fn finds_swaps_going_both_ways() {
+        let removed = [asks(vec![(OTHER_GLYPH, ScVal::Void)])];
+        let owner_changes = [(GLYPH, SELLER, BUYER), (OTHER_GLYPH, BUYER, SELLER)];
+
+        assert_eq!(
+            sold(&removed, &[], &owner_changes),
+            Some((OTHER_GLYPH, ScVal::Void))
+        );
+    }
+
+    #[test]
+    fn ignores_swaps_when_only_one_glyph_moved() {
+        let removed = [asks(vec![(OTHER_GLYPH, ScVal::Void)])];
+        let owner_changes = [(GLYPH, SELLER, BUYER)];
+
+        assert_eq!(sold(&removed, &[], &owner_changes), None);
+    }
+
+    #[test]
+    fn ignores_plain_transfers() {
+        // A transfer wipes the asks too, but the buyer didn't send any of them in
+        let removed = [asks(vec![(ASSET, AMOUNT)])];
+
+        assert_eq!(sold(&removed, &[], &[(GLYPH, SELLER, BUYER)]), None);
+    }
+
+    #[test]
+    fn ignores_other_glyphs() {
+        let removed = [RemovedOffer::Bids {
+            glyph: OTHER_GLYPH,
+            asset: ASSET,
+            amount: AMOUNT,
+            bidders: vec![BUYER],
+        }];
+
+        assert_eq!(sold(&removed, &[], &[]), None);
+    }
+
+    fn offer(seller: ScVal, selling: ScVal, buying: ScVal, amount: ScVal) -> ZephyrOfferNoStatus {
+        ZephyrOfferNoStatus {
+            id: ScVal::Void,
+            seller,
+            selling,
+            buying,
+            amount,
+            validity: ScVal::Void,
+        }
+    }
+
+    fn sale(asset: ScVal, amount: ScVal) -> ZephyrSale {
+        ZephyrSale {
+            glyph: GLYPH,
+            seller: SELLER,
+            buyer: BUYER,
+            asset,
+            amount,
+            ledger: 1,
+            timestamp: ScVal::U64(1),
+            tx_hash: ScVal::Void,
+        }
+    }
+
+    #[test]
+    fn closes_filled_asks_as_filled() {
+        let ask = offer(SELLER, GLYPH, ASSET, AMOUNT);
+        let sales = [sale(ASSET, AMOUNT)];
+
+        assert_eq!(
+            get_close_reason(&sales, &[(GLYPH, SELLER, BUYER)], &ask),
+            OfferStatus::Filled
+        );
+    }
+
+    #[test]
+    fn closes_filled_bids_as_filled() {
+        let bid = offer(BUYER, ASSET, GLYPH, AMOUNT);
+        let sales = [sale(ASSET, AMOUNT)];
+
+        assert_eq!(
+            get_close_reason(&sales, &[(GLYPH, SELLER, BUYER)], &bid),
+            OfferStatus::Filled
+        );
+    }
+
+    #[test]
+    fn closes_filled_swaps_as_filled() {
+        let swap = offer(SELLER, GLYPH, OTHER_GLYPH, ScVal::Void);
+        let sales = [sale(OTHER_GLYPH, ScVal::Void)];
+        let owner_changes = [(GLYPH, SELLER, BUYER), (OTHER_GLYPH, BUYER, SELLER)];
+
+        assert_eq!(
+            get_close_reason(&sales, &owner_changes, &swap),
+            OfferStatus::Filled
+        );
+    }
+
+    #[test]
+    fn closes_offers_on_a_transferred_glyph_as_invalidated() {
+        let ask = offer(SELLER, GLYPH, ASSET, AMOUNT);
+        let bid = offer(ScVal::U32(22), ASSET, GLYPH, AMOUNT);
+        let owner_changes = [(GLYPH, SELLER, BUYER)];
+
+        assert_eq!(
+            get_close_reason(&[], &owner_changes, &ask),
+            OfferStatus::Invalidated
+        );
+        assert_eq!(
+            get_close_reason(&[], &owner_changes, &bid),
+            OfferStatus::Invalidated
+        );
+    }
+
+    #[test]
+    fn closes_other_offers_filled_in_the_same_sale_as_invalidated() {
+        // The same glyph's other ask didn't sell, it just went with the glyph
+        let other_ask = offer(SELLER, GLYPH, ASSET, ScVal::U32(50));
+        let sales = [sale(ASSET, AMOUNT)];
+
+        assert_eq!(
+            get_close_reason(&sales, &[(GLYPH, SELLER, BUYER)], &other_ask),
+            OfferStatus::Invalidated
+        );
+    }
+
+    #[test]
+    fn closes_offers_otherwise_as_cancelled() {
+        let ask = offer(SELLER, GLYPH, ASSET, AMOUNT);
+
+        assert_eq!(get_close_reason(&[], &[], &ask), OfferStatus::Cancelled);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::types::ZephyrOfferNoStatus;
+
+/* Each swap is an edge from the glyph being sold to the glyph wanted in return.
+    A trade is possible when those edges loop back around: A's owner wants B and B's owner wants A,
+    or further out, A's owner wants B, B's owner wants C and C's owner wants A.
+    Returns the offers making up each cycle, in trade order.
+*/
+pub fn find_swap_cycles(swaps: &Vec<ZephyrOfferNoStatus>, max_length: usize) -> Vec<Vec<usize>> {
+    let mut cycles = Vec::new();
+
+    for start in 0..swaps.len() {
+        extend_cycle(swaps, max_length, &mut vec![start], &mut cycles);
+    }
+
+    cycles
+}
+
+// Cycles only ever start from their lowest offer so each one is found once
+fn extend_cycle(
+    swaps: &Vec<ZephyrOfferNoStatus>,
+    max_length: usize,
+    path: &mut Vec<usize>,
+    cycles: &mut Vec<Vec<usize>>,
+) {
+    let first = &swaps[path[0]];
+    let last = &swaps[path[path.len() - 1]];
+
+    if last.buying == first.selling {
+        cycles.push(path.clone());
+        return;
+    }
+
+    if path.len() >= max_length {
+        return;
+    }
+
+    for next in path[0] + 1..swaps.len() {
+        let swap = &swaps[next];
+
+        // Every glyph and every owner takes part once
+        let visited = path.iter().any(|&index| {
+            swaps[index].selling == swap.selling || swaps[index].seller == swap.seller
+        });
+
+        if swap.selling == last.buying && !visited {
+            path.push(next);
+            extend_cycle(swaps, max_length, path, cycles);
+            path.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_swap_cycles;
+    use crate::types::ZephyrOfferNoStatus;
+    use zephyr_sdk::soroban_sdk::xdr::ScVal;
+
+    fn swap(seller: u32, selling: u32, buying: u32) -> ZephyrOfferNoStatus {
+        ZephyrOfferNoStatus {
+            id: ScVal::U32(selling),
+            seller: ScVal::U32(seller),
+            selling: ScVal::U32(selling),
+            buying: ScVal::U32(buying),
+            amount: ScVal::Void,
+            validity: ScVal::Void,
+        }
+    }
+
+    #[test]
+    fn finds_reciprocal_swaps_once() {
+        let swaps = vec![swap(1, 10, 20), swap(2, 20, 10)];
+
+        assert_eq!(find_swap_cycles(&swaps, 2), vec![vec![0, 1]]);
+    }
+
+    #[test]
+    fn finds_three_way_cycles_once_in_trade_order() {
+        let swaps = vec![swap(2, 20, 30), swap(1, 10, 20), swap(3, 30, 10)];
+
+        assert_eq!(find_swap_cycles(&swaps, 3), vec![vec![0, 2, 1]]);
+    }
+
+    #[test]
+    fn skips_cycles_longer_than_max_length() {
+        let swaps = vec![swap(1, 10, 20), swap(2, 20, 30), swap(3, 30, 10)];
+
+        assert!(find_swap_cycles(&swaps, 2).is_empty());
+    }
+
+    #[test]
+    fn skips_sellers_trading_with_themselves() {
+        let swaps = vec![swap(1, 10, 20), swap(1, 20, 10)];
+
+        assert!(find_swap_cycles(&swaps, 2).is_empty());
+    }
+
+    #[test]
+    fn finds_every_distinct_cycle() {
+        let swaps = vec![
+            swap(1, 10, 20),
+            swap(2, 20, 10),
+            swap(3, 30, 40),
+            swap(4, 40, 50),
+            swap(5, 50, 30),
+        ];
+
+        assert_eq!(find_swap_cycles(&swaps, 3), vec![vec![0, 1], vec![2, 3, 4]]);
+    }
+}
@@ -1,8 +1,11 @@
 use colorglyph::types::Offer;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use zephyr_sdk::{
     prelude::*,
-    soroban_sdk::{xdr::ScVal, Address, Vec as SorobanVec},
+    soroban_sdk::{
+        xdr::{ScSymbol, ScVal},
+        Address, Vec as SorobanVec,
+    },
     DatabaseDerive, EnvClient,
 };
 
@@ -38,24 +41,58 @@ pub struct ZephyrGlyph {
     pub width: u32,
     pub length: u32,
     pub colors: ScVal,
+    pub color_count: u32,
+    pub dominant_colors: ScVal,
+    pub transparent_bps: u32,
+    pub phash: ScVal,    // U64
+    pub verified: ScVal, // Bool, whether the hash matches the glyph's colors and width, Void until minted
+    pub mint_state: ScVal,
+    pub mint_steps: u32, // how many times the glyph entry was written while minting
+    pub minted_ledger: u32,
 }
 
 #[derive(DatabaseDerive, Clone, Serialize, Debug)]
 #[with_name("glyphs")]
 pub struct ZephyrGlyphNoColors {
-    hash: ScVal,
-    owner: ScVal,
-    minter: ScVal,
-    width: u32,
-    length: u32,
+    pub hash: ScVal,
+    pub owner: ScVal,
+    pub minter: ScVal,
+    pub width: u32,
+    pub length: u32,
+    pub color_count: u32,
+    pub dominant_colors: ScVal,
+    pub transparent_bps: u32,
+    pub phash: ScVal,    // U64
+    pub verified: ScVal, // Bool, whether the hash matches the glyph's colors and width, Void until minted
+    pub mint_state: ScVal,
+    pub mint_steps: u32, // how many times the glyph entry was written while minting
+    pub minted_ledger: u32,
 }
 
+// Everything a write to the glyph's entry changes, in one update
 #[derive(DatabaseDerive, Clone, Serialize, Debug)]
 #[with_name("glyphs")]
-pub struct ZephyrGlyphWidthLengthColors {
+pub struct ZephyrGlyphPainted {
     pub width: u32,
     pub length: u32,
     pub colors: ScVal,
+    pub color_count: u32,
+    pub dominant_colors: ScVal, // Vec of [color, pixels], most used first
+    pub transparent_bps: u32,   // share of unpainted pixels in basis points
+    pub phash: ScVal,
+    pub verified: ScVal,
+    pub mint_state: ScVal,
+    pub mint_steps: u32,
+    pub minted_ledger: u32,
+}
+
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("glyphs")]
+pub struct ZephyrGlyphMint {
+    pub mint_state: ScVal,
+    pub mint_steps: u32,
+    pub minted_ledger: u32,
+    pub verified: ScVal, // set when the mint completes and left alone after
 }
 
 #[derive(DatabaseDerive, Clone, Serialize, Debug)]
@@ -74,39 +111,328 @@ pub struct ZephyrGlyphMinter {
 #[with_name("glyphs")]
 pub struct ZephyrGlyphEmpty {}
 
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("glyph_renders")]
+pub struct ZephyrGlyphRender {
+    pub hash: ScVal,
+    pub format: ScVal, // svg or png
+    pub scale: u32,
+    pub render: ScVal, // String for svg, Bytes for png, Void once the glyph has been repainted
+}
+
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("glyph_renders")]
+pub struct ZephyrGlyphRenderOutput {
+    pub render: ScVal,
+}
+
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("glyph_renders")]
+pub struct ZephyrGlyphRenderEmpty {}
+
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("glyph_colors")]
+pub struct ZephyrGlyphColor {
+    pub hash: ScVal,
+    pub color: u32,
+    pub pixels: u32,
+    pub coverage_bps: u32, // share of the glyph's pixels in basis points
+}
+
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("glyph_colors")]
+pub struct ZephyrGlyphColorCoverage {
+    pub pixels: u32,
+    pub coverage_bps: u32,
+}
+
+// Glyphs are painted over several calls and only count as minted once they have an owner
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MintState {
+    Minting,
+    Minted,
+}
+
+impl MintState {
+    pub fn to_scval(&self) -> ScVal {
+        let state = match self {
+            MintState::Minting => "minting",
+            MintState::Minted => "minted",
+        };
+
+        ScVal::Symbol(ScSymbol(state.try_into().unwrap()))
+    }
+}
+
 // --- OFFERS ---
 
 #[derive(DatabaseDerive, Clone, Serialize, Debug)]
 #[with_name("offers")]
 pub struct ZephyrOffer {
+    pub id: ScVal,
     pub seller: ScVal,
     pub selling: ScVal,
     pub buying: ScVal,
     pub amount: ScVal, // because currently i128 is broken
-    pub active: ScVal,
+    pub status: ScVal,
+    pub validity: ScVal,
+    pub posted_ledger: u32,
+    pub closed_ledger: u32,
 }
 
 #[derive(DatabaseDerive, Clone, Serialize, Debug)]
 #[with_name("offers")]
-pub struct ZephyrOfferNoActive {
-    seller: ScVal,
-    selling: ScVal,
-    buying: ScVal,
-    amount: ScVal, // because currently i128 is broken
+pub struct ZephyrOfferNoStatus {
+    pub id: ScVal,
+    pub seller: ScVal,
+    pub selling: ScVal,
+    pub buying: ScVal,
+    pub amount: ScVal, // because currently i128 is broken
+    pub validity: ScVal,
 }
 
 #[derive(DatabaseDerive, Clone, Serialize, Debug)]
 #[with_name("offers")]
-pub struct ZephyrOfferActive {
-    pub active: ScVal,
+pub struct ZephyrOfferStatus {
+    pub status: ScVal,
+}
+
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("offers")]
+pub struct ZephyrOfferPosted {
+    pub status: ScVal,
+    pub validity: ScVal,
+    pub posted_ledger: u32,
+    pub closed_ledger: u32,
+}
+
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("offers")]
+pub struct ZephyrOfferClosed {
+    pub status: ScVal,
+    pub closed_ledger: u32,
+}
+
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("offers")]
+pub struct ZephyrOfferValidity {
+    pub validity: ScVal,
 }
 
 #[derive(DatabaseDerive, Clone, Serialize, Debug)]
 #[with_name("offers")]
 pub struct ZephyrOfferEmpty {}
 
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("offer_events")]
+pub struct ZephyrOfferEvent {
+    pub id: ScVal,
+    pub seller: ScVal,
+    pub selling: ScVal,
+    pub buying: ScVal,
+    pub amount: ScVal, // because currently i128 is broken
+    pub status: ScVal,
+    pub ledger: u32,
+    pub timestamp: ScVal, // U64 seconds of the ledger close
+}
+
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("offer_events")]
+pub struct ZephyrOfferEventEmpty {}
+
+// The asset side of an offer, glyph for glyph swaps have none
+pub trait OfferAsset {
+    fn asset(&self) -> Option<&ScVal>;
+    fn amount(&self) -> &ScVal;
+}
+
+impl OfferAsset for ZephyrOffer {
+    fn asset(&self) -> Option<&ScVal> {
+        offer_asset(&self.selling, &self.buying)
+    }
+
+    fn amount(&self) -> &ScVal {
+        &self.amount
+    }
+}
+
+impl OfferAsset for ZephyrOfferNoStatus {
+    fn asset(&self) -> Option<&ScVal> {
+        offer_asset(&self.selling, &self.buying)
+    }
+
+    fn amount(&self) -> &ScVal {
+        &self.amount
+    }
+}
+
+// Glyphs are hashes, assets are contract addresses
+fn offer_asset<'a>(selling: &'a ScVal, buying: &'a ScVal) -> Option<&'a ScVal> {
+    [selling, buying]
+        .into_iter()
+        .find(|val| matches!(val, ScVal::Address(_)))
+}
+
+// Anything but `Active` is closed, and the status says why
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OfferStatus {
+    Active,
+    Cancelled,
+    Filled,
+    Invalidated, // the glyph changed hands without the offer being filled
+}
+
+impl OfferStatus {
+    pub fn to_scval(&self) -> ScVal {
+        let status = match self {
+            OfferStatus::Active => "active",
+            OfferStatus::Cancelled => "cancelled",
+            OfferStatus::Filled => "filled",
+            OfferStatus::Invalidated => "invalidated",
+        };
+
+        ScVal::Symbol(ScSymbol(status.try_into().unwrap()))
+    }
+}
+
+// Active offers which can't currently be filled are kept but flagged
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OfferValidity {
+    Valid,
+    Stale,    // the seller no longer owns the glyph, or the bidder already does
+    Unfunded, // the bidder doesn't hold enough of the asset they're bidding with
+    Unknown,  // the bidder's balance hasn't been seen yet
+}
+
+impl OfferValidity {
+    pub fn to_scval(&self) -> ScVal {
+        let validity = match self {
+            OfferValidity::Valid => "valid",
+            OfferValidity::Stale => "stale",
+            OfferValidity::Unfunded => "unfunded",
+            OfferValidity::Unknown => "unknown",
+        };
+
+        ScVal::Symbol(ScSymbol(validity.try_into().unwrap()))
+    }
+}
+
+// --- SALES ---
+
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("sales")]
+pub struct ZephyrSale {
+    pub glyph: ScVal,
+    pub seller: ScVal,
+    pub buyer: ScVal,
+    pub asset: ScVal,  // the glyph hash for swaps
+    pub amount: ScVal, // because currently i128 is broken
+    pub ledger: u32,
+    pub timestamp: ScVal, // U64 seconds of the ledger close
+    pub tx_hash: ScVal,
+}
+
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("sales")]
+pub struct ZephyrSaleEmpty {}
+
+// --- MATCHES ---
+
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("matches")]
+pub struct ZephyrMatch {
+    pub id: ScVal,
+    pub glyph: ScVal,
+    pub asset: ScVal,
+    pub seller: ScVal,
+    pub buyer: ScVal,
+    pub ask_amount: ScVal, // because currently i128 is broken
+    pub bid_amount: ScVal,
+    pub status: ScVal,
+}
+
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("matches")]
+pub struct ZephyrMatchStatus {
+    pub status: ScVal,
+}
+
+// Open while the bid is still at or above the ask
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchStatus {
+    Open,
+    Closed,
+}
+
+impl MatchStatus {
+    pub fn to_scval(&self) -> ScVal {
+        let status = match self {
+            MatchStatus::Open => "open",
+            MatchStatus::Closed => "closed",
+        };
+
+        ScVal::Symbol(ScSymbol(status.try_into().unwrap()))
+    }
+}
+
+// --- ASSETS ---
+
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("assets")]
+pub struct ZephyrAsset {
+    pub address: ScVal,
+    pub name: ScVal,
+    pub symbol: ScVal,
+    pub decimals: u32,
+}
+
+// --- BIDDER BALANCES ---
+
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("bidder_balances")]
+pub struct ZephyrBidderBalance {
+    pub address: ScVal,
+    pub asset: ScVal,
+    pub balance: ScVal, // because currently i128 is broken, Void until first seen
+    pub ledger: u32,
+}
+
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("bidder_balances")]
+pub struct ZephyrBidderAddress {
+    pub address: ScVal,
+}
+
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("bidder_balances")]
+pub struct ZephyrBidderBalanceAmount {
+    pub balance: ScVal,
+    pub ledger: u32,
+}
+
+// --- LATEST LEDGER ---
+
+// A single row, the last ledger the indexer closed
+#[derive(DatabaseDerive, Clone, Serialize, Debug)]
+#[with_name("latest_ledger")]
+pub struct ZephyrLatestLedger {
+    pub ledger: u32,
+    pub timestamp: ScVal, // U64 seconds of the ledger close
+}
+
 // --- OTHER ---
 
+#[derive(Clone, Debug)]
+pub struct TransactionContext {
+    pub ledger: u32,
+    pub timestamp: u64,
+    pub tx_hash: ScVal,
+}
+
 #[derive(Clone, Debug)]
 pub enum Offers {
     Offers(SorobanVec<Offer>),